@@ -0,0 +1,114 @@
+//! Throughput of cache lookups under concurrent load, compared with previous design
+//! guarding single LRU with upgradable read lock
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+
+#[allow(dead_code)]
+#[path = "../src/simple_cache.rs"]
+mod simple_cache;
+
+use simple_cache::ConcurrentCache;
+
+const KEYS: u64 = 256;
+const LOOKUPS_PER_THREAD: u64 = 10_000;
+
+/// Previous `OptimisticLRU`
+struct UpgradableLru {
+    cache: RwLock<lru::LruCache<u64, Arc<String>>>,
+}
+
+impl UpgradableLru {
+    fn get_or_update(&self, key: u64, update: impl FnOnce(&u64) -> String) -> Arc<String> {
+        let cache = self.cache.upgradable_read();
+        if let Some(content) = cache.peek(&key) {
+            content.clone()
+        } else {
+            let mut cache = RwLockUpgradableReadGuard::upgrade(cache);
+            let content = Arc::new(update(&key));
+            cache.put(key, content.clone());
+            content
+        }
+    }
+}
+
+/// Stand-in for template render done on miss
+fn render(key: &u64) -> String {
+    thread::sleep(Duration::from_micros(50));
+    format!("<span>user {}</span>", key)
+}
+
+fn run_threads(threads: u64, lookup: Arc<dyn Fn(u64) + Send + Sync>) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|thread| {
+            let lookup = lookup.clone();
+            thread::spawn(move || {
+                for i in 0..LOOKUPS_PER_THREAD {
+                    lookup((i * 7 + thread) % KEYS);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn concurrent_lookups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_lookups");
+    group.sample_size(20);
+    for threads in [1, 4, 8, 16].iter().copied() {
+        group.bench_with_input(
+            BenchmarkId::new("upgradable_lru", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| {
+                            let cache = Arc::new(UpgradableLru {
+                                cache: RwLock::new(lru::LruCache::new(KEYS as usize)),
+                            });
+                            run_threads(
+                                threads,
+                                Arc::new(move |key| {
+                                    cache.get_or_update(key, render);
+                                }),
+                            )
+                        })
+                        .sum()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("concurrent_cache", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| {
+                            let cache = Arc::new(ConcurrentCache::new(KEYS as usize * 2));
+                            run_threads(
+                                threads,
+                                Arc::new(move |key| {
+                                    cache.get_or_insert_with(key, render);
+                                }),
+                            )
+                        })
+                        .sum()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_lookups);
+criterion_main!(benches);
@@ -0,0 +1,478 @@
+//! Append-only log of who accessed what. Every entry contains hash of previous one,
+//! so modified or removed entries are detected by `verify`.
+
+mod routes;
+
+use std::{
+    fs::{read_dir, read_to_string, rename, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use anyhow::{anyhow, Context as _, Result};
+use chrono::{DateTime, Utc};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{auth::Identity, config::config, metrics};
+
+pub use routes::configure;
+
+static AUDIT_LOG: OnceCell<AuditLog> = OnceCell::new();
+
+/// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub user: Option<String>,
+    pub method: String,
+    pub path: String,
+    /// Matched route pattern
+    pub route: Option<String>,
+    pub status: u16,
+    pub channel: Option<String>,
+    /// Requested day, or `from..to` for ranges
+    pub date: Option<String>,
+    /// Query string, contains search terms and export options
+    pub query: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditRecord {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditRecord {
+    fn compute_hash(seq: u64, event: &AuditEvent, prev_hash: &str) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(prev_hash.as_bytes());
+        hasher.update(seq.to_string().as_bytes());
+        hasher.update(&serde_json::to_vec(event)?);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Result of checking hash chain of all log files
+#[derive(Serialize, Debug)]
+pub struct ChainStatus {
+    pub entries: u64,
+    /// Description of first invalid entry
+    pub broken: Option<String>,
+}
+
+struct State {
+    file: File,
+    size: u64,
+    seq: u64,
+    last_hash: String,
+    /// Rotated files from oldest
+    rotated: Vec<PathBuf>,
+}
+
+/// Log files as they were when taken, so they can be read without holding the lock
+struct Snapshot {
+    rotated: Vec<PathBuf>,
+    path: PathBuf,
+    /// Opened before the file can be rotated, only `current_len` bytes are complete entries
+    current: File,
+    current_len: u64,
+}
+
+impl Snapshot {
+    /// Number of files, rotated ones followed by current one
+    fn len(&self) -> usize {
+        self.rotated.len() + 1
+    }
+
+    /// Path and content of `index`-th file
+    fn read(&self, index: usize) -> Result<(&Path, String)> {
+        if let Some(file) = self.rotated.get(index) {
+            let content = read_to_string(file)
+                .with_context(|| format!("Failed to read audit log {}", file.display()))?;
+            return Ok((file, content));
+        }
+        let mut current = &self.current;
+        current.seek(SeekFrom::Start(0))?;
+        let mut content = String::new();
+        current
+            .take(self.current_len)
+            .read_to_string(&mut content)
+            .with_context(|| format!("Failed to read audit log {}", self.path.display()))?;
+        Ok((&self.path, content))
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self> {
+        let (seq, last_hash) = match last_record(path)? {
+            Some(record) => (record.seq, record.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        let mut rotated = log_files(path)?;
+        rotated.retain(|file| file != path);
+        Ok(Self {
+            path: path.to_owned(),
+            max_bytes,
+            state: Mutex::new(State {
+                file,
+                size,
+                seq,
+                last_hash,
+                rotated,
+            }),
+        })
+    }
+
+    fn snapshot(&self) -> Result<Snapshot> {
+        let state = self.state.lock();
+        Ok(Snapshot {
+            rotated: state.rotated.clone(),
+            path: self.path.clone(),
+            current: File::open(&self.path)
+                .with_context(|| format!("Failed to open audit log {}", self.path.display()))?,
+            current_len: state.size,
+        })
+    }
+
+    pub fn append(&self, event: AuditEvent) -> Result<()> {
+        let mut state = self.state.lock();
+        let seq = state.seq + 1;
+        let hash = AuditRecord::compute_hash(seq, &event, &state.last_hash)?;
+        let record = AuditRecord {
+            seq,
+            event,
+            prev_hash: state.last_hash.clone(),
+            hash,
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        if state.size > 0 && state.size + line.len() as u64 > self.max_bytes {
+            let rotated = format!(
+                "{}.{}",
+                self.path.display(),
+                Utc::now().format("%Y%m%d%H%M%S%6f")
+            );
+            rename(&self.path, &rotated)
+                .with_context(|| format!("Failed to rotate audit log to {}", rotated))?;
+            state.file = open_append(&self.path)?;
+            state.size = 0;
+            state.rotated.push(rotated.into());
+        }
+
+        state
+            .file
+            .write_all(line.as_bytes())
+            .context("Failed to write audit log")?;
+        state.size += line.len() as u64;
+        state.seq = seq;
+        state.last_hash = record.hash;
+        Ok(())
+    }
+
+    /// Records matching `filter`, newest first, at most `limit`. Files are read without
+    /// blocking `append`, entries written meanwhile are not included.
+    pub fn query(
+        &self,
+        filter: impl Fn(&AuditRecord) -> bool,
+        limit: usize,
+    ) -> Result<Vec<AuditRecord>> {
+        let snapshot = self.snapshot()?;
+        let mut records = Vec::new();
+        for index in (0..snapshot.len()).rev() {
+            let (file, content) = snapshot.read(index)?;
+            let mut matching: Vec<_> = parse_records(file, &content)?
+                .into_iter()
+                .filter(|record| filter(record))
+                .collect();
+            while let Some(record) = matching.pop() {
+                if records.len() == limit {
+                    return Ok(records);
+                }
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Check hash chain of all entries, has to read whole log
+    pub fn verify(&self) -> Result<ChainStatus> {
+        verify(&self.snapshot()?)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log {}", path.display()))
+}
+
+/// Rotated files sorted from oldest, followed by current file
+fn log_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid audit log path {}", path.display()))?;
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.", name);
+    let mut rotated: Vec<PathBuf> = read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .map_or(false, |file_name| file_name.starts_with(&prefix))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    rotated.sort();
+    if path.exists() {
+        rotated.push(path.to_owned());
+    }
+    Ok(rotated)
+}
+
+fn read_records(file: &Path) -> Result<Vec<AuditRecord>> {
+    let content = read_to_string(file)
+        .with_context(|| format!("Failed to read audit log {}", file.display()))?;
+    parse_records(file, &content)
+}
+
+fn parse_records(file: &Path, content: &str) -> Result<Vec<AuditRecord>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(line, content)| {
+            serde_json::from_str(content)
+                .with_context(|| format!("Invalid audit entry {}:{}", file.display(), line + 1))
+        })
+        .collect()
+}
+
+fn last_record(path: &Path) -> Result<Option<AuditRecord>> {
+    for file in log_files(path)?.iter().rev() {
+        if let Some(record) = read_records(file)?.pop() {
+            return Ok(Some(record));
+        }
+    }
+    Ok(None)
+}
+
+fn verify(snapshot: &Snapshot) -> Result<ChainStatus> {
+    let mut status = ChainStatus {
+        entries: 0,
+        broken: None,
+    };
+    let mut last_hash = GENESIS_HASH.to_string();
+    for index in 0..snapshot.len() {
+        let (file, content) = snapshot.read(index)?;
+        for (line, content) in content.lines().enumerate() {
+            let location = format!("{}:{}", file.display(), line + 1);
+            let record: AuditRecord = match serde_json::from_str(content) {
+                Ok(record) => record,
+                Err(err) => {
+                    status.broken = Some(format!("{} is not valid entry: {}", location, err));
+                    return Ok(status);
+                }
+            };
+            let problem = if record.seq != status.entries + 1 {
+                Some(format!("expected seq {}", status.entries + 1))
+            } else if record.prev_hash != last_hash {
+                Some("previous hash does not match".to_string())
+            } else if AuditRecord::compute_hash(record.seq, &record.event, &last_hash)?
+                != record.hash
+            {
+                Some("content does not match hash".to_string())
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                status.broken = Some(format!("{}: {}", location, problem));
+                return Ok(status);
+            }
+            status.entries += 1;
+            last_hash = record.hash;
+        }
+    }
+    Ok(status)
+}
+
+/// Open log configured in `[audit]` section, nothing is recorded when it is missing
+pub fn init() -> Result<()> {
+    let audit = &config().audit;
+    if let Some(path) = &audit.path {
+        let log = AuditLog::open(path, audit.max_bytes)?;
+        AUDIT_LOG
+            .set(log)
+            .map_err(|_| anyhow!("Audit log already initialized"))?;
+    }
+    Ok(())
+}
+
+pub fn audit_log() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get()
+}
+
+fn record_response<B>(log: &AuditLog, res: &ServiceResponse<B>) {
+    let req = res.request();
+    let params = req.match_info();
+    let date = params.get("date").map(str::to_string).or_else(|| {
+        match (params.get("from"), params.get("to")) {
+            (Some(from), Some(to)) => Some(format!("{}..{}", from, to)),
+            _ => None,
+        }
+    });
+    // authorization code and state of login callback are not worth keeping
+    let query = Some(req.query_string())
+        .filter(|query| !query.is_empty() && !req.path().starts_with("/auth/"))
+        .map(str::to_string);
+    let event = AuditEvent {
+        time: Utc::now(),
+        user: req
+            .extensions()
+            .get::<Identity>()
+            .map(|identity| identity.username.clone()),
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        route: req.match_pattern(),
+        status: res.status().as_u16(),
+        channel: params.get("channel").map(str::to_string),
+        date,
+        query,
+    };
+    if let Err(err) = log.append(event) {
+        log::error!("Failed to record audit entry: {:#}", err);
+    }
+}
+
+/// Middleware recording every request except static files and probes, must be wrapped by
+/// `Authentication` to know the user
+pub struct AuditTrail;
+
+impl<S, B> Transform<S> for AuditTrail
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditTrailMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditTrailMiddleware { service })
+    }
+}
+
+pub struct AuditTrailMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for AuditTrailMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let log = audit_log()
+            .filter(|_| !req.path().starts_with("/static/") && !metrics::is_monitoring(req.path()));
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let res = response.await?;
+            if let Some(log) = log {
+                record_response(log, &res);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(user: &str) -> AuditEvent {
+        AuditEvent {
+            time: Utc::now(),
+            user: Some(user.to_string()),
+            method: "GET".to_string(),
+            path: "/C01/2020-01-01".to_string(),
+            route: Some("/{channel}/{date}".to_string()),
+            status: 200,
+            channel: Some("C01".to_string()),
+            date: Some("2020-01-01".to_string()),
+            query: None,
+        }
+    }
+
+    #[test]
+    fn test_hash_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        // small limit to rotate on every entry
+        let log = AuditLog::open(&path, 1).unwrap();
+        log.append(event("alice")).unwrap();
+        log.append(event("bob")).unwrap();
+        drop(log);
+        let log = AuditLog::open(&path, 1).unwrap();
+        log.append(event("carol")).unwrap();
+        assert_eq!(log_files(&path).unwrap().len(), 3);
+        assert_eq!(log.verify().unwrap().entries, 3);
+        assert!(log.verify().unwrap().broken.is_none());
+
+        let newest = log.query(|_| true, 2).unwrap();
+        assert_eq!(newest.len(), 2);
+        assert_eq!(newest[0].event.user.as_deref(), Some("carol"));
+
+        let first = &log_files(&path).unwrap()[0];
+        let tampered = read_to_string(first).unwrap().replace("alice", "mallory");
+        std::fs::write(first, tampered).unwrap();
+        assert!(log.verify().unwrap().broken.is_some());
+    }
+}
@@ -0,0 +1,115 @@
+use actix_web::{
+    get,
+    web::{self, ServiceConfig},
+    HttpResponse,
+};
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{audit_log, AuditRecord, ChainStatus};
+use crate::{
+    authz::{Access, Viewer},
+    error::{Result, WebError},
+    ui::{layout_context, render_page, sidebar::SidebarPrefs, LayoutContext},
+    DataMessagesReader,
+};
+
+/// Maximum number of entries shown on page
+const PAGE_LIMIT: usize = 500;
+
+#[derive(Deserialize, Serialize, Default)]
+struct AuditQuery {
+    user: Option<String>,
+    channel: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    /// Check hash chain, it reads the whole log so it is not done on every view
+    verify: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditContext<'a> {
+    layout: LayoutContext<'a>,
+    query: &'a AuditQuery,
+    entries: Vec<AuditRecord>,
+    limit: usize,
+    chain: Option<ChainStatus>,
+}
+
+/// Empty form fields are sent as empty strings
+fn field(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_date(value: &Option<String>) -> Result<Option<NaiveDate>> {
+    field(value)
+        .map(|date| {
+            date.parse()
+                .map_err(|_| WebError::BadRequest("Invalid date".to_string()))
+        })
+        .transpose()
+}
+
+#[get("/admin/audit")]
+async fn audit_page(
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse> {
+    if !matches!(viewer.access, Access::All) {
+        return Err(WebError::Forbidden);
+    }
+    let log = audit_log().ok_or(WebError::Disabled("Audit log"))?;
+
+    let user = field(&query.user).map(str::to_string);
+    let channel = field(&query.channel).map(|channel| {
+        reader
+            .resolve_channel(channel)
+            .map_or(channel.to_string(), |channel| channel.id.clone())
+    });
+    let from = parse_date(&query.from)?.map(|date| Utc.from_utc_date(&date).and_hms(0, 0, 0));
+    let to = parse_date(&query.to)?.map(|date| Utc.from_utc_date(&date.succ()).and_hms(0, 0, 0));
+
+    let verify = field(&query.verify).is_some();
+
+    // whole log can be read, so it is not done on async worker
+    let (entries, chain) = web::block(move || -> anyhow::Result<_> {
+        let entries = log.query(
+            |record| {
+                let event = &record.event;
+                user.as_ref()
+                    .map_or(true, |user| event.user.as_ref() == Some(user))
+                    && channel
+                        .as_ref()
+                        .map_or(true, |channel| event.channel.as_ref() == Some(channel))
+                    && from.map_or(true, |from| event.time >= from)
+                    && to.map_or(true, |to| event.time < to)
+            },
+            PAGE_LIMIT,
+        )?;
+        let chain = if verify { Some(log.verify()?) } else { None };
+        Ok((entries, chain))
+    })
+    .await?;
+
+    let page = render_page(
+        "audit.tera",
+        &AuditContext {
+            layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+            query: &query,
+            entries,
+            limit: PAGE_LIMIT,
+            chain,
+        },
+    )
+    .map_err(WebError::Template)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(page))
+}
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(audit_page);
+}
@@ -0,0 +1,269 @@
+mod oidc;
+mod routes;
+
+use std::{
+    collections::HashMap,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, Method},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use futures::future::{ok, ready, Either, Ready};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    config::{config, AuthProvider},
+    error::WebError,
+    metrics,
+};
+
+pub use routes::configure;
+
+pub const SESSION_COOKIE: &str = "slack_archive_session";
+
+lazy_static! {
+    static ref SESSIONS: SessionStore = SessionStore::default();
+}
+
+/// Authenticated user
+#[derive(Serialize, Clone, Debug)]
+pub struct Identity {
+    pub username: String,
+    pub email: Option<String>,
+}
+
+impl FromRequest for Identity {
+    type Config = ();
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Identity>()
+                .cloned()
+                .ok_or_else(|| WebError::Unauthorized("Not logged in")),
+        )
+    }
+}
+
+struct Session {
+    identity: Identity,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct SessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl SessionStore {
+    fn create(&self, identity: Identity) -> String {
+        let id: String = rand::thread_rng()
+            .gen::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let ttl = Duration::from_secs(config().auth.session_hours * 3600);
+        let mut sessions = self.sessions.write();
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                identity,
+                expires: now + ttl,
+            },
+        );
+        id
+    }
+
+    fn get(&self, id: &str) -> Option<Identity> {
+        self.sessions
+            .read()
+            .get(id)
+            .filter(|session| session.expires > Instant::now())
+            .map(|session| session.identity.clone())
+    }
+
+    fn remove(&self, id: &str) {
+        self.sessions.write().remove(id);
+    }
+}
+
+/// Random token for one time values like OIDC state
+fn random_token() -> String {
+    rand::thread_rng()
+        .gen::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Verify password against encoded argon2 hash
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(password_hash, password.as_bytes()).unwrap_or_else(|err| {
+        log::warn!("Invalid password hash in config: {}", err);
+        false
+    })
+}
+
+/// Encoded argon2id hash with random salt, used by `hash-password` command
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = rand::thread_rng().gen::<[u8; 16]>();
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..argon2::Config::default()
+    };
+    Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config)?)
+}
+
+fn identify(provider: &AuthProvider, req: &ServiceRequest) -> Option<Identity> {
+    match provider {
+        AuthProvider::None => None,
+        AuthProvider::Header {
+            user_header,
+            email_header,
+            trusted_proxies,
+        } => {
+            let trusted = req
+                .peer_addr()
+                .map_or(false, |peer| trusted_proxies.contains(&peer.ip()));
+            if !trusted {
+                log::debug!("Ignoring identity headers from {:?}", req.peer_addr());
+                return None;
+            }
+            let header_value = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.to_string())
+            };
+            header_value(user_header).map(|username| Identity {
+                username,
+                email: email_header.as_deref().and_then(header_value),
+            })
+        }
+        AuthProvider::Static { .. } | AuthProvider::Oidc(_) => req
+            .cookie(SESSION_COOKIE)
+            .and_then(|cookie| SESSIONS.get(cookie.value())),
+    }
+}
+
+/// Paths reachable without logging in
+fn is_public(path: &str) -> bool {
+    metrics::is_monitoring(path)
+        || path == "/login" || path == "/logout" || path.starts_with("/auth/") || path.starts_with("/static/")
+}
+
+fn unauthenticated(req: &ServiceRequest) -> HttpResponse {
+    let redirect = !matches!(config().auth.provider, AuthProvider::Header { .. })
+        && req.method() == Method::GET;
+    if redirect {
+        let next = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        let query = serde_urlencoded::to_string(&[("next", next)]).unwrap_or_default();
+        HttpResponse::Found()
+            .header(header::LOCATION, format!("/login?{}", query))
+            .finish()
+    } else {
+        HttpResponse::Unauthorized().body("Authentication required")
+    }
+}
+
+/// Middleware resolving `Identity` of request, requests without one are sent to login
+/// page unless authentication is disabled
+pub struct Authentication;
+
+impl<S, B> Transform<S> for Authentication
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthenticationMiddleware { service })
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for AuthenticationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if let AuthProvider::None = config().auth.provider {
+            return Either::Left(self.service.call(req));
+        }
+
+        match identify(&config().auth.provider, &req) {
+            Some(identity) => {
+                req.extensions_mut().insert(identity);
+                Either::Left(self.service.call(req))
+            }
+            None if is_public(req.path()) => Either::Left(self.service.call(req)),
+            None => {
+                let response = unauthenticated(&req);
+                Either::Right(ok(req.into_response(response.into_body())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_header_from_trusted_proxy_only() {
+        let provider = AuthProvider::Header {
+            user_header: "X-Forwarded-User".to_string(),
+            email_header: None,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+        };
+        let request = |peer: &str| {
+            TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .header("X-Forwarded-User", "alice")
+                .to_srv_request()
+        };
+
+        let identity = identify(&provider, &request("10.0.0.1:4000")).unwrap();
+        assert_eq!(identity.username, "alice");
+        assert!(identify(&provider, &request("10.0.0.2:4000")).is_none());
+    }
+}
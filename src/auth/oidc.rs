@@ -0,0 +1,232 @@
+//! OpenID Connect authorization code flow, identity is taken from userinfo endpoint
+//! so tokens do not need to be verified locally
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use actix_web::client::Client;
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{random_token, Identity};
+use crate::config::OidcConfig;
+
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    static ref DISCOVERY: RwLock<Option<Discovery>> = RwLock::new(None);
+    static ref PENDING_LOGINS: RwLock<HashMap<String, PendingLogin>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Deserialize, Clone)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+struct PendingLogin {
+    next: String,
+    started: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+async fn discover(config: &OidcConfig) -> Result<Discovery> {
+    if let Some(discovery) = DISCOVERY.read().as_ref() {
+        return Ok(discovery.clone());
+    }
+
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer.trim_end_matches('/')
+    );
+    let discovery: Discovery = Client::default()
+        .get(&url)
+        .send()
+        .await
+        .map_err(|err| anyhow!("Failed to fetch {}: {}", url, err))?
+        .json()
+        .await
+        .map_err(|err| anyhow!("Invalid discovery document {}: {}", url, err))?;
+    *DISCOVERY.write() = Some(discovery.clone());
+    Ok(discovery)
+}
+
+/// Url of identity provider login page, `next` is where user returns after login
+pub async fn authorization_url(config: &OidcConfig, next: String) -> Result<String> {
+    let discovery = discover(config).await?;
+    let state = random_token();
+    {
+        let mut pending = PENDING_LOGINS.write();
+        pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                next,
+                started: Instant::now(),
+            },
+        );
+    }
+    let query = serde_urlencoded::to_string(&[
+        ("response_type", "code"),
+        ("client_id", &config.client_id),
+        ("redirect_uri", &config.redirect_url),
+        ("scope", &config.scopes),
+        ("state", &state),
+    ])?;
+    Ok(format!("{}?{}", discovery.authorization_endpoint, query))
+}
+
+/// Finish login started by `authorization_url`, returns identity and page to return to
+pub async fn complete_login(
+    config: &OidcConfig,
+    code: &str,
+    state: &str,
+) -> Result<(Identity, String)> {
+    let pending = PENDING_LOGINS
+        .write()
+        .remove(state)
+        .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+        .ok_or_else(|| anyhow!("Unknown or expired login state"))?;
+    let discovery = discover(config).await?;
+    let client = Client::default();
+
+    let token: TokenResponse = client
+        .post(&discovery.token_endpoint)
+        .send_form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_url),
+            ("client_id", &config.client_id),
+            ("client_secret", &config.client_secret),
+        ])
+        .await
+        .map_err(|err| anyhow!("Token request failed: {}", err))?
+        .json()
+        .await
+        .map_err(|err| anyhow!("Invalid token response: {}", err))?;
+
+    let userinfo: HashMap<String, Value> = client
+        .get(&discovery.userinfo_endpoint)
+        .bearer_auth(&token.access_token)
+        .send()
+        .await
+        .map_err(|err| anyhow!("Userinfo request failed: {}", err))?
+        .json()
+        .await
+        .map_err(|err| anyhow!("Invalid userinfo response: {}", err))?;
+
+    let claim = |name: &str| userinfo.get(name).and_then(|value| value.as_str());
+    let username = claim(&config.username_claim)
+        .or_else(|| claim("sub"))
+        .context("Userinfo response without subject")?
+        .to_string();
+    Ok((
+        Identity {
+            username,
+            email: claim("email").map(|email| email.to_string()),
+        },
+        pending.next,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use serde_json::json;
+
+    use super::*;
+
+    const CODE: &str = "code-1";
+    const ACCESS_TOKEN: &str = "token-1";
+    const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+
+    /// Endpoint and query parameters of authorization url
+    fn split_url(url: &str) -> (&str, HashMap<String, String>) {
+        let (endpoint, query) = url.split_at(url.find('?').unwrap());
+        (endpoint, serde_urlencoded::from_str(&query[1..]).unwrap())
+    }
+
+    async fn discovery(req: HttpRequest) -> HttpResponse {
+        let base = format!("http://{}", req.connection_info().host());
+        HttpResponse::Ok().json(json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{}/authorize", base),
+            "token_endpoint": format!("{}/token", base),
+            "userinfo_endpoint": format!("{}/userinfo", base),
+        }))
+    }
+
+    async fn token(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+        let field = |name: &str| form.get(name).map(String::as_str);
+        if field("grant_type") == Some("authorization_code")
+            && field("code") == Some(CODE)
+            && field("client_id") == Some("archive")
+            && field("client_secret") == Some("secret")
+        {
+            HttpResponse::Ok().json(json!({"access_token": ACCESS_TOKEN, "token_type": "Bearer"}))
+        } else {
+            HttpResponse::BadRequest().json(json!({"error": "invalid_grant"}))
+        }
+    }
+
+    async fn userinfo(req: HttpRequest) -> HttpResponse {
+        let expected = format!("Bearer {}", ACCESS_TOKEN);
+        match req.headers().get("authorization") {
+            Some(value) if *value == *expected => HttpResponse::Ok().json(json!({
+                "sub": "1234",
+                "preferred_username": "alice",
+                "email": "alice@example.com",
+            })),
+            _ => HttpResponse::Unauthorized().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_login_flow() {
+        let provider = test::start(|| {
+            App::new()
+                .route(DISCOVERY_PATH, web::get().to(discovery))
+                .route("/token", web::post().to(token))
+                .route("/userinfo", web::get().to(userinfo))
+        });
+        let config = OidcConfig {
+            issuer: provider.url("/"),
+            client_id: "archive".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "https://archive.example.com/auth/callback".to_string(),
+            scopes: "openid email".to_string(),
+            username_claim: "preferred_username".to_string(),
+        };
+
+        let url = authorization_url(&config, "/C1".to_string()).await.unwrap();
+        let (endpoint, query) = split_url(&url);
+        assert_eq!(endpoint, provider.url("/authorize"));
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], "archive");
+        assert_eq!(query["redirect_uri"], config.redirect_url);
+        let state = &query["state"];
+
+        assert!(complete_login(&config, CODE, "forged").await.is_err());
+        let (identity, next) = complete_login(&config, CODE, state).await.unwrap();
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(next, "/C1");
+        // state can be used only once
+        assert!(complete_login(&config, CODE, state).await.is_err());
+
+        // rejected code fails login
+        let url = authorization_url(&config, "/".to_string()).await.unwrap();
+        let state = &split_url(&url).1["state"];
+        assert!(complete_login(&config, "wrong", state).await.is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    get,
+    http::header,
+    post,
+    web::{self, ServiceConfig},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{oidc, verify_password, Identity, SESSIONS, SESSION_COOKIE};
+use crate::{
+    config::{config, AuthProvider},
+    error::{Result, WebError},
+    ui::{render_page, LayoutContext},
+};
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+#[derive(Serialize)]
+struct LoginContext<'a> {
+    layout: LayoutContext<'static>,
+    next: &'a str,
+    error: Option<&'a str>,
+}
+
+/// Only local paths are accepted as return address to avoid open redirects. Browsers
+/// treat `\` as `/` and drop tabs and newlines, so `/\host` or `/<tab>/host` would
+/// lead to another site.
+fn safe_next(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next[1..].starts_with('/')
+                && !next.contains(|c: char| c == '\\' || c.is_control()) =>
+        {
+            next
+        }
+        _ => "/",
+    }
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+fn start_session(identity: Identity, next: &str) -> HttpResponse {
+    log::info!("User {} logged in", identity.username);
+    let session = SESSIONS.create(identity);
+    HttpResponse::Found()
+        .header(header::LOCATION, next)
+        .cookie(
+            Cookie::build(SESSION_COOKIE, session)
+                .path("/")
+                .http_only(true)
+                .secure(config().auth.secure_cookie)
+                .same_site(SameSite::Lax)
+                .finish(),
+        )
+        .finish()
+}
+
+fn render_login(next: &str, error: Option<&str>) -> Result<HttpResponse> {
+    let page = render_page(
+        "login.tera",
+        &LoginContext {
+            layout: LayoutContext::empty(),
+            next,
+            error,
+        },
+    )
+    .map_err(WebError::Template)?;
+    let response = if error.is_some() {
+        HttpResponse::Unauthorized()
+    } else {
+        HttpResponse::Ok()
+    }
+    .content_type("text/html")
+    .body(page);
+    Ok(response)
+}
+
+#[get("/login")]
+async fn login_page(query: web::Query<LoginQuery>) -> Result<HttpResponse> {
+    let next = safe_next(query.next.as_deref());
+    match &config().auth.provider {
+        AuthProvider::Static { .. } => render_login(next, None),
+        AuthProvider::Oidc(oidc_config) => Ok(redirect(
+            &oidc::authorization_url(oidc_config, next.to_string()).await?,
+        )),
+        AuthProvider::None | AuthProvider::Header { .. } => Ok(redirect(next)),
+    }
+}
+
+#[post("/login")]
+async fn login(form: web::Form<LoginForm>) -> Result<HttpResponse> {
+    let next = safe_next(form.next.as_deref());
+    let users = match &config().auth.provider {
+        AuthProvider::Static { users } => users,
+        _ => return Err(WebError::Disabled("Password login")),
+    };
+
+    let user = users.iter().find(|user| user.username == form.username);
+    match user {
+        Some(user) if verify_password(&user.password_hash, &form.password) => {
+            Ok(start_session(
+                Identity {
+                    username: user.username.clone(),
+                    email: user.email.clone(),
+                },
+                next,
+            ))
+        }
+        _ => {
+            log::info!("Failed login attempt for {}", form.username);
+            render_login(next, Some("Invalid user name or password"))
+        }
+    }
+}
+
+#[get("/auth/callback")]
+async fn oidc_callback(query: web::Query<CallbackQuery>) -> Result<HttpResponse> {
+    let oidc_config = match &config().auth.provider {
+        AuthProvider::Oidc(oidc_config) => oidc_config,
+        _ => return Err(WebError::Disabled("OpenID Connect")),
+    };
+    let (identity, next) = oidc::complete_login(oidc_config, &query.code, &query.state)
+        .await
+        .map_err(|err| {
+            log::warn!("OpenID Connect login failed: {:#}", err);
+            WebError::Unauthorized("Login failed")
+        })?;
+    let next = safe_next(Some(&next)).to_string();
+    Ok(start_session(identity, &next))
+}
+
+#[get("/logout")]
+async fn logout(req: HttpRequest) -> HttpResponse {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        SESSIONS.remove(cookie.value());
+    }
+    HttpResponse::Found()
+        .header(header::LOCATION, "/login")
+        .del_cookie(&Cookie::build(SESSION_COOKIE, "").path("/").finish())
+        .finish()
+}
+
+/// Register login routes, they need to go before catch-all ui routes
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(login_page)
+        .service(login)
+        .service(oidc_callback)
+        .service(logout);
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_safe_next() {
+        assert_eq!(safe_next(Some("/C1/2020-10-11?x=1")), "/C1/2020-10-11?x=1");
+        assert_eq!(safe_next(Some("/")), "/");
+        assert_eq!(safe_next(None), "/");
+        for unsafe_next in &[
+            "//evil.com",
+            "/\\evil.com",
+            "\\\\evil.com",
+            "/x\\..\\\\evil.com",
+            "/\t/evil.com",
+            "https://evil.com",
+            "evil.com",
+            "",
+        ] {
+            assert_eq!(safe_next(Some(unsafe_next)), "/", "{}", unsafe_next);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_session_and_logout() {
+        test_support::init_config();
+        let session = SESSIONS.create(Identity {
+            username: "alice".to_string(),
+            email: None,
+        });
+        assert_eq!(SESSIONS.get(&session).unwrap().username, "alice");
+        assert!(SESSIONS.get("unknown").is_none());
+
+        let mut app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::get()
+            .uri("/logout")
+            .cookie(Cookie::new(SESSION_COOKIE, session.clone()))
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/login");
+        let removal = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == SESSION_COOKIE)
+            .unwrap();
+        assert_eq!(removal.value(), "");
+        assert!(SESSIONS.get(&session).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+//! Which conversations logged in user is allowed to see
+
+use std::collections::HashSet;
+
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use futures::future::{ok, Ready};
+
+use crate::{
+    auth::Identity,
+    config::{config, AuthProvider},
+    error::WebError,
+    reader::{ChannelInfo, MessagesReader},
+    READER,
+};
+
+#[derive(Debug, Clone)]
+pub enum Access {
+    All,
+    Restricted {
+        /// Slack account matched by email, grants access to conversations it is member of
+        slack_user_id: Option<String>,
+        /// Channels granted by group rules
+        channels: HashSet<String>,
+    },
+}
+
+impl Access {
+    pub fn resolve(reader: &MessagesReader, identity: Option<&Identity>) -> Self {
+        if let AuthProvider::None = config().auth.provider {
+            return Access::All;
+        }
+        let identity = match identity {
+            Some(identity) => identity,
+            None => {
+                return Access::Restricted {
+                    slack_user_id: None,
+                    channels: HashSet::new(),
+                }
+            }
+        };
+        let matches = |principal: &String| {
+            *principal == identity.username || Some(principal) == identity.email.as_ref()
+        };
+
+        let authz = &config().authz;
+        if authz.admins.iter().any(matches) {
+            return Access::All;
+        }
+
+        let mut channels = HashSet::new();
+        for group in authz
+            .groups
+            .iter()
+            .filter(|group| group.users.iter().any(matches))
+        {
+            for channel in &group.channels {
+                if channel == "*" {
+                    return Access::All;
+                }
+                match reader.resolve_channel(channel) {
+                    Ok(channel) => {
+                        channels.insert(channel.id.clone());
+                    }
+                    Err(_) => log::warn!("Unknown channel {} in group {}", channel, group.name),
+                }
+            }
+        }
+
+        Access::Restricted {
+            slack_user_id: identity
+                .email
+                .as_deref()
+                .and_then(|email| reader.find_user_by_email(email))
+                .map(|user| user.id.clone()),
+            channels,
+        }
+    }
+
+    pub fn can_view(&self, channel: &ChannelInfo) -> bool {
+        match self {
+            Access::All => true,
+            Access::Restricted {
+                slack_user_id,
+                channels,
+            } => {
+                channels.contains(&channel.id)
+                    || slack_user_id
+                        .as_ref()
+                        .map_or(false, |user_id| channel.members.contains(user_id))
+            }
+        }
+    }
+
+    pub fn can_view_id(&self, reader: &MessagesReader, channel_id: &str) -> bool {
+        reader
+            .get_channel(channel_id)
+            .map_or(false, |channel| self.can_view(channel))
+    }
+
+    pub fn visible_channels<'a>(&self, reader: &'a MessagesReader) -> Vec<&'a ChannelInfo> {
+        reader
+            .list_channels()
+            .into_iter()
+            .filter(|channel| self.can_view(channel))
+            .collect()
+    }
+}
+
+/// Identity of request together with its access rights
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    pub identity: Option<Identity>,
+    pub access: Access,
+}
+
+impl Viewer {
+    /// Used outside of web requests, e.g. by exporters
+    pub fn unrestricted() -> Self {
+        Self {
+            identity: None,
+            access: Access::All,
+        }
+    }
+}
+
+impl FromRequest for Viewer {
+    type Config = ();
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let identity = req.extensions().get::<Identity>().cloned();
+        ok(Self {
+            access: Access::resolve(&READER.current(), identity.as_ref()),
+            identity,
+        })
+    }
+}
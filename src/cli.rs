@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use structopt::StructOpt;
+
+use crate::export::{flat::FlatFormat, text::TextFormat, ExportFilter};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Slack Archive Browser")]
+pub struct Opts {
+    /// Config file, defaults are used when it does not exist
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "SLACK_ARCHIVE_CONFIG",
+        default_value = "config.toml"
+    )]
+    pub config: PathBuf,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Start web server (default)
+    Serve,
+    /// Read password from stdin and print argon2 hash for static users config
+    HashPassword,
+    /// Render whole archive as static HTML site
+    ExportHtml {
+        /// Output directory
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Export channel history as Markdown or plain text
+    ExportText {
+        /// Channel id or name
+        #[structopt(long)]
+        channel: String,
+        /// First day to export
+        #[structopt(long)]
+        from: Option<NaiveDate>,
+        /// Last day to export
+        #[structopt(long)]
+        to: Option<NaiveDate>,
+        /// Output format: markdown or plain
+        #[structopt(long, default_value = "markdown")]
+        format: TextFormat,
+        /// Write directory with one file per day instead of single document
+        #[structopt(long)]
+        split_days: bool,
+        /// Output file, or directory with --split-days
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Export messages as mbox, one email per message
+    ExportMbox {
+        #[structopt(flatten)]
+        filter: ExportFilter,
+        /// Directory with downloaded files, stored as <file id>/<file name>, to embed as attachments
+        #[structopt(long, parse(from_os_str))]
+        files_dir: Option<PathBuf>,
+        /// Output mbox file
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Export messages as flat CSV or JSON Lines rows for analytics
+    ExportFlat {
+        #[structopt(flatten)]
+        filter: ExportFilter,
+        /// Output format: csv or jsonl
+        #[structopt(long, default_value = "csv")]
+        format: FlatFormat,
+        /// Output file, `-` for stdout
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Convert archive to Mattermost bulk import JSONL
+    ExportMattermost {
+        #[structopt(flatten)]
+        filter: ExportFilter,
+        /// Name of Mattermost team to import into
+        #[structopt(long)]
+        team: String,
+        /// Display name of the team, defaults to its name
+        #[structopt(long)]
+        team_display_name: Option<String>,
+        /// Directory with downloaded files, stored as <file id>/<file name>, to import as attachments
+        #[structopt(long, parse(from_os_str))]
+        files_dir: Option<PathBuf>,
+        /// Output JSONL file
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Export conversations as Matrix room events for appservice based import
+    ExportMatrix {
+        #[structopt(flatten)]
+        filter: ExportFilter,
+        /// Homeserver name used in generated user ids and room aliases
+        #[structopt(long)]
+        server_name: String,
+        /// Output directory
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// List day files outside of retention policy and legal holds, as `<path>\t<reason>`
+    /// with paths relative to data directory
+    RetentionReport,
+    /// Check data directory for broken day files, missing folders, unknown users and
+    /// duplicate messages, exits with non-zero code when errors are found
+    Validate {
+        /// Print machine-readable JSON report
+        #[structopt(long)]
+        json: bool,
+        /// Fail on warnings too
+        #[structopt(long)]
+        strict: bool,
+    },
+    /// Create or incrementally update SQLite index of the export, only changed day files
+    /// are parsed again
+    Index {
+        /// Index file, defaults to `index_path` from config
+        #[structopt(long, parse(from_os_str))]
+        index: Option<PathBuf>,
+    },
+    /// Report message fields, subtypes and block types the message model does not cover
+    SchemaReport {
+        /// Print machine-readable JSON report
+        #[structopt(long)]
+        json: bool,
+    },
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs::read_to_string,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// Directory with unpacked Slack export
+    pub data_path: PathBuf,
+    /// SQLite index built by `index` command, used instead of day files when set
+    pub index_path: Option<PathBuf>,
+    pub bind: String,
+    /// Memory for parsed days, counted as size of their files, 0 disables the cache
+    pub day_cache_bytes: usize,
+    pub auth: AuthConfig,
+    pub authz: AuthzConfig,
+    pub audit: AuditConfig,
+    pub redaction: RedactionConfig,
+    pub retention: RetentionConfig,
+    pub reload: ReloadConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_path: PathBuf::from("./data"),
+            index_path: None,
+            bind: "127.0.0.1:8080".to_string(),
+            day_cache_bytes: 64 * 1024 * 1024,
+            auth: AuthConfig::default(),
+            authz: AuthzConfig::default(),
+            audit: AuditConfig::default(),
+            redaction: RedactionConfig::default(),
+            retention: RetentionConfig::default(),
+            reload: ReloadConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// Reload when data directory, or index when used, changes
+    pub watch: bool,
+    /// Wait for changes to settle before reloading
+    pub delay_secs: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            delay_secs: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub provider: AuthProvider,
+    /// Lifetime of login session
+    pub session_hours: u64,
+    /// Send session cookie only over https
+    pub secure_cookie: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            provider: AuthProvider::None,
+            session_hours: 12,
+            secure_cookie: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthProvider {
+    /// Everyone can see everything
+    None,
+    /// Users with argon2 password hashes listed in config
+    Static { users: Vec<StaticUser> },
+    /// Identity set by authenticating reverse proxy
+    Header {
+        user_header: String,
+        email_header: Option<String>,
+        /// Peer addresses headers are accepted from, anyone else could set them
+        #[serde(default = "AuthProvider::default_trusted_proxies")]
+        trusted_proxies: Vec<IpAddr>,
+    },
+    /// OpenID Connect authorization code flow
+    Oidc(OidcConfig),
+}
+
+impl AuthProvider {
+    fn default_trusted_proxies() -> Vec<IpAddr> {
+        vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StaticUser {
+    pub username: String,
+    /// Encoded argon2 hash, see `hash-password` command
+    pub password_hash: String,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OidcConfig {
+    /// Issuer url, `/.well-known/openid-configuration` is appended for discovery
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Public url of `/auth/callback` route
+    pub redirect_url: String,
+    #[serde(default = "OidcConfig::default_scopes")]
+    pub scopes: String,
+    /// Userinfo claim used as user name, `sub` is used when missing
+    #[serde(default = "OidcConfig::default_username_claim")]
+    pub username_claim: String,
+}
+
+impl OidcConfig {
+    fn default_scopes() -> String {
+        "openid email profile".to_string()
+    }
+
+    fn default_username_claim() -> String {
+        "preferred_username".to_string()
+    }
+}
+
+/// Authorization rules, users are matched by login name or email. Without matching
+/// rule users see conversations their Slack account (matched by email) is member of.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct AuthzConfig {
+    /// Users allowed to see everything
+    pub admins: Vec<String>,
+    pub groups: Vec<GroupRule>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GroupRule {
+    pub name: String,
+    pub users: Vec<String>,
+    /// Channel ids or names, `*` grants all channels
+    pub channels: Vec<String>,
+}
+
+/// Log of requests made by users, disabled when `path` is not set
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Current log file, rotated files get timestamp suffix
+    pub path: Option<PathBuf>,
+    /// Size after which log file is rotated
+    pub max_bytes: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// Masking of personal data in pages, API and exports
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub rules: Vec<RedactionRule>,
+    /// User ids shown under pseudonym, `*` for everyone
+    pub pseudonymize_users: Vec<String>,
+    /// Mixed into pseudonyms so they can't be matched to user ids
+    pub pseudonym_salt: String,
+    /// Channel ids or names whose messages are shown unredacted
+    pub exempt_channels: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RedactionRule {
+    pub name: String,
+    /// Regular expression matched against message text
+    pub pattern: String,
+    /// Text put in place of match, may refer to groups as `$1`
+    #[serde(default = "RedactionRule::default_replacement")]
+    pub replacement: String,
+}
+
+impl RedactionRule {
+    fn default_replacement() -> String {
+        "[redacted]".to_string()
+    }
+}
+
+/// Days outside of policy are hidden unless covered by legal hold
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Policy of channels not listed in `channels`
+    pub default: Option<RetentionPolicy>,
+    pub channels: Vec<ChannelPolicy>,
+    pub holds: Vec<LegalHold>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Keep only days not older than this
+    pub keep_days: Option<u32>,
+    /// Keep only days inside one of ranges, when any is set
+    pub ranges: Vec<DateRange>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChannelPolicy {
+    /// Channel ids or names
+    pub channels: Vec<String>,
+    #[serde(flatten)]
+    pub policy: RetentionPolicy,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LegalHold {
+    pub name: String,
+    /// Channel ids or names, `*` for all
+    pub channels: Vec<String>,
+    #[serde(flatten)]
+    pub range: DateRange,
+}
+
+/// Inclusive range of days, open when bound is missing
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        toml::from_str(
+            &read_to_string(path)
+                .with_context(|| format!("Failed to read config {}", path.display()))?,
+        )
+        .with_context(|| format!("Failed to parse config {}", path.display()))
+    }
+}
+
+/// Load config from `path`, default config is used when file does not exist
+pub fn init(path: &Path) -> Result<()> {
+    let config = if path.exists() {
+        Config::load(path)?
+    } else {
+        log::info!("Config {} not found, using defaults", path.display());
+        Config::default()
+    };
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("Config already initialized"))
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -0,0 +1,238 @@
+//! Report of export content not covered by message model. Every message is deserialized
+//! into `Entry` and serialized back, keys lost on the way are the ones serde dropped.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::reader::{Entry, MessagesReader};
+
+/// How many example locations are kept per finding
+const EXAMPLES: usize = 3;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Finding {
+    pub count: usize,
+    /// Locations as `<day file>[<message index>]`
+    pub examples: Vec<String>,
+}
+
+impl Finding {
+    fn record(&mut self, location: &str) {
+        self.count += 1;
+        if self.examples.len() < EXAMPLES {
+            self.examples.push(location.to_string());
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DriftReport {
+    pub day_files: usize,
+    pub messages: usize,
+    /// Messages which can't be deserialized as `Entry` at all, by error
+    pub unparsed: BTreeMap<String, Finding>,
+    /// Field paths dropped during deserialization, arrays marked as `[]`
+    pub unknown_fields: BTreeMap<String, Finding>,
+    /// Message subtypes, none of them has dedicated handling
+    pub subtypes: BTreeMap<String, Finding>,
+    /// Types of blocks and their nested elements
+    pub block_types: BTreeMap<String, Finding>,
+}
+
+impl DriftReport {
+    pub fn print_text(&self) {
+        println!(
+            "Checked {} day files with {} messages",
+            self.day_files, self.messages
+        );
+        for (title, findings) in &[
+            ("Unparsed messages", &self.unparsed),
+            ("Unknown fields", &self.unknown_fields),
+            ("Subtypes", &self.subtypes),
+            ("Block types", &self.block_types),
+        ] {
+            if findings.is_empty() {
+                continue;
+            }
+            println!("\n{}:", title);
+            for (name, finding) in findings.iter() {
+                println!(
+                    "  {} ({}x), e.g. {}",
+                    name,
+                    finding.count,
+                    finding.examples.join(", ")
+                );
+            }
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Paths of keys present in `original` but missing in `roundtrip`
+fn missing_keys(original: &Value, roundtrip: &Value, path: &str, out: &mut BTreeSet<String>) {
+    match (original, roundtrip) {
+        (Value::Object(original), Value::Object(roundtrip)) => {
+            for (key, value) in original {
+                let path = join(path, key);
+                match roundtrip.get(key) {
+                    Some(kept) => missing_keys(value, kept, &path, out),
+                    None => {
+                        out.insert(path);
+                    }
+                }
+            }
+        }
+        (Value::Array(original), Value::Array(roundtrip)) => {
+            for (value, kept) in original.iter().zip(roundtrip) {
+                missing_keys(value, kept, &format!("{}[]", path), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// All key paths of `value`
+fn key_paths(value: &Value, path: &str, out: &mut HashSet<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let path = join(path, key);
+                key_paths(value, &path, out);
+                out.insert(path);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                key_paths(value, &format!("{}[]", path), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Record `type` of every object nested in blocks
+fn block_types(value: &Value, location: &str, report: &mut DriftReport) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(block_type)) = object.get("type") {
+                report
+                    .block_types
+                    .entry(block_type.clone())
+                    .or_default()
+                    .record(location);
+            }
+            for value in object.values() {
+                block_types(value, location, report);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                block_types(value, location, report);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn drift_report(reader: &MessagesReader, data_path: &std::path::Path) -> Result<DriftReport> {
+    let mut report = DriftReport::default();
+    // fields serialized back at least once are modeled, even if sometimes skipped as empty
+    let mut known = HashSet::new();
+    let mut missing: BTreeMap<String, Finding> = BTreeMap::new();
+
+    let mut channels = reader.list_channels();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for channel in channels {
+        let mut dates = match reader.list_stored_dates(&channel.id) {
+            Ok(dates) => dates,
+            Err(err) => {
+                log::warn!("Skipping channel {}: {:#}", channel.name, err);
+                continue;
+            }
+        };
+        dates.sort();
+        for date in dates {
+            let file = reader.day_file(channel, date);
+            let messages: Vec<Value> = match std::fs::read_to_string(data_path.join(&file))
+                .context("Failed to read day")
+                .and_then(|content| Ok(serde_json::from_str(&content)?))
+            {
+                Ok(messages) => messages,
+                Err(err) => {
+                    log::warn!("Skipping {}: {:#}", file.display(), err);
+                    continue;
+                }
+            };
+            report.day_files += 1;
+
+            for (index, message) in messages.into_iter().enumerate() {
+                report.messages += 1;
+                let location = format!("{}[{}]", file.display(), index);
+                if let Some(Value::String(subtype)) = message.get("subtype") {
+                    report
+                        .subtypes
+                        .entry(subtype.clone())
+                        .or_default()
+                        .record(&location);
+                }
+                if let Some(blocks) = message.get("blocks") {
+                    block_types(blocks, &location, &mut report);
+                }
+
+                let entry: Entry = match serde_json::from_value(message.clone()) {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        report
+                            .unparsed
+                            .entry(err.to_string())
+                            .or_default()
+                            .record(&location);
+                        continue;
+                    }
+                };
+                let roundtrip = serde_json::to_value(&entry)?;
+                key_paths(&roundtrip, "", &mut known);
+                // each path once per message, even when missing in many array items
+                let mut paths = BTreeSet::new();
+                missing_keys(&message, &roundtrip, "", &mut paths);
+                for path in paths {
+                    missing.entry(path).or_default().record(&location);
+                }
+            }
+        }
+    }
+
+    report.unknown_fields = missing
+        .into_iter()
+        .filter(|(path, _)| !known.contains(path))
+        .collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_keys() {
+        let original = serde_json::json!({
+            "a": 1,
+            "b": [{"c": 1, "d": 2}, {"d": 3}, {"c": 1, "d": 4}],
+            "e": {"f": 1}
+        });
+        let roundtrip = serde_json::json!({"a": 1, "b": [{"c": 1}, {}, {"c": 1}]});
+        let mut paths = BTreeSet::new();
+        missing_keys(&original, &roundtrip, "", &mut paths);
+        assert_eq!(paths.into_iter().collect::<Vec<_>>(), vec!["b[].d", "e"]);
+    }
+}
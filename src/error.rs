@@ -1,41 +1,398 @@
-use std::{fmt::Display, result};
+use std::{
+    any::Any,
+    fmt::{Debug, Display},
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    result,
+    task::{Context, Poll},
+};
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    body::{Body, MessageBody, ResponseBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::{BlockingError, ResponseError},
+    http::{header, HeaderName, HeaderValue},
+    Error, HttpMessage, HttpResponse,
+};
+use futures::{
+    future::{ok, Ready},
+    FutureExt,
+};
+use rand::Rng;
+use serde::Serialize;
 
-use actix_web::error::ResponseError;
 pub use actix_web::http::StatusCode;
 
+use crate::ui::{render_page, LayoutContext};
+
 #[derive(Debug)]
-pub struct WebError {
-    err: anyhow::Error,
-    code: StatusCode,
+pub enum WebError {
+    /// Unknown route
+    PageNotFound,
+    /// Channel does not exist or viewer can't see it
+    ChannelNotFound,
+    /// Channel has no history for day, or day is outside of retention
+    DateNotFound,
+    UserNotFound,
+    /// Feature turned off in config
+    Disabled(&'static str),
+    BadRequest(String),
+    Unauthorized(&'static str),
+    Forbidden,
+    /// Data computed in background is not available yet
+    NotReady(&'static str),
+    /// Export content which can't be read or parsed
+    CorruptData(anyhow::Error),
+    Template(anyhow::Error),
+    Internal(anyhow::Error),
 }
+
 impl WebError {
-    pub fn new(code: StatusCode, msg: &str) -> Self {
-        Self {
-            code,
-            err: anyhow::anyhow!("{}", msg),
+    /// Stable identifier of error for API clients
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::PageNotFound => "page_not_found",
+            Self::ChannelNotFound => "channel_not_found",
+            Self::DateNotFound => "date_not_found",
+            Self::UserNotFound => "user_not_found",
+            Self::Disabled(_) => "disabled",
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::NotReady(_) => "not_ready",
+            Self::CorruptData(_) => "corrupt_data",
+            Self::Template(_) => "template",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    /// Message safe to show to users, internal details are only logged
+    pub fn message(&self) -> String {
+        match self {
+            Self::PageNotFound => "Page not found".to_string(),
+            Self::ChannelNotFound => "Channel not found".to_string(),
+            Self::DateNotFound => "No messages on this day".to_string(),
+            Self::UserNotFound => "User not found".to_string(),
+            Self::Disabled(what) => format!("{} is disabled", what),
+            Self::BadRequest(msg) => msg.clone(),
+            Self::Unauthorized(msg) => (*msg).to_string(),
+            Self::Forbidden => "Only for admins".to_string(),
+            Self::NotReady(what) => format!("{} not available yet, try again shortly", what),
+            Self::CorruptData(_) => "Archive data for this page is damaged".to_string(),
+            Self::Template(_) => "Failed to render the page".to_string(),
+            Self::Internal(_) => "Internal server error".to_string(),
         }
     }
 }
 
 impl Display for WebError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:#}", self.err))
+        match self {
+            Self::CorruptData(err) | Self::Template(err) | Self::Internal(err) => {
+                write!(f, "{}: {:#}", self.message(), err)
+            }
+            _ => f.write_str(&self.message()),
+        }
     }
 }
 
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: &'a str,
+    status: u16,
+    request_id: Option<&'a str>,
+}
+
 impl ResponseError for WebError {
     fn status_code(&self) -> StatusCode {
-        self.code
+        match self {
+            Self::PageNotFound
+            | Self::ChannelNotFound
+            | Self::DateNotFound
+            | Self::UserNotFound
+            | Self::Disabled(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotReady(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::CorruptData(_) | Self::Template(_) | Self::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Plain JSON, replaced by `ErrorPages` with page fitting the request
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.kind(),
+            message: &self.message(),
+            status: self.status_code().as_u16(),
+            request_id: None,
+        })
     }
 }
 
 impl From<anyhow::Error> for WebError {
     fn from(err: anyhow::Error) -> Self {
-        Self {
-            err,
-            code: StatusCode::INTERNAL_SERVER_ERROR,
+        Self::Internal(err)
+    }
+}
+
+impl From<BlockingError<anyhow::Error>> for WebError {
+    fn from(err: BlockingError<anyhow::Error>) -> Self {
+        match err {
+            BlockingError::Error(err) => Self::Internal(err),
+            BlockingError::Canceled => {
+                Self::Internal(anyhow::anyhow!("Blocking task was canceled"))
+            }
         }
     }
 }
 
 pub type Result<T, E = WebError> = result::Result<T, E>;
+
+/// Id of request shown on error pages and sent in `X-Request-Id`, so reports can be
+/// matched with logs
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+const REQUEST_ID: &str = "x-request-id";
+
+impl RequestId {
+    /// Id set by proxy in front, or new random one
+    fn of(req: &ServiceRequest) -> Self {
+        let forwarded = req
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= 64
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        Self(match forwarded {
+            Some(id) => id.to_string(),
+            None => format!("{:016x}", rand::thread_rng().gen::<u64>()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorContext<'a> {
+    layout: LayoutContext<'static>,
+    status: u16,
+    reason: &'a str,
+    message: &'a str,
+    request_id: &'a str,
+}
+
+/// API clients get JSON, everything else a page
+fn wants_json(req: &ServiceRequest) -> bool {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    req.path().starts_with("/api/")
+        || (accept.contains("application/json") && !accept.contains("text/html"))
+}
+
+/// Error rendered for request it happened in, as page or JSON
+struct RenderedError {
+    status: StatusCode,
+    content_type: &'static str,
+    body: String,
+    request_id: String,
+}
+
+impl RenderedError {
+    fn new(json: bool, status: StatusCode, kind: &str, message: &str, request_id: &str) -> Self {
+        let body = if json {
+            serde_json::to_string(&ErrorBody {
+                error: kind,
+                message,
+                status: status.as_u16(),
+                request_id: Some(request_id),
+            })
+            .map(|body| ("application/json", body))
+            .map_err(anyhow::Error::from)
+        } else {
+            render_page(
+                "error.tera",
+                &ErrorContext {
+                    layout: LayoutContext::empty(),
+                    status: status.as_u16(),
+                    reason: status.canonical_reason().unwrap_or("Error"),
+                    message,
+                    request_id,
+                },
+            )
+            .map(|body| ("text/html", body))
+        };
+        let (content_type, body) = body.unwrap_or_else(|err| {
+            log::error!("[{}] Failed to render error: {:#}", request_id, err);
+            ("text/plain", message.to_string())
+        });
+        Self {
+            status,
+            content_type,
+            body,
+            request_id: request_id.to_string(),
+        }
+    }
+
+    /// Rendered error from handler, extractor or inner middleware
+    fn of(json: bool, err: &Error, request_id: &str) -> Self {
+        let status = err.as_response_error().status_code();
+        match err.as_error::<WebError>() {
+            Some(err) => Self::new(json, status, err.kind(), &err.message(), request_id),
+            // errors of actix extractors, like invalid path or query
+            None => {
+                let kind = if status == StatusCode::NOT_FOUND {
+                    "page_not_found"
+                } else {
+                    "bad_request"
+                };
+                Self::new(json, status, kind, &err.to_string(), request_id)
+            }
+        }
+    }
+}
+
+impl Display for RenderedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in request {}", self.status, self.request_id)
+    }
+}
+
+// actix logs server errors with debug format, body would only clutter it
+impl Debug for RenderedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl ResponseError for RenderedError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .content_type(self.content_type)
+            .header(REQUEST_ID, self.request_id.as_str())
+            .body(self.body.clone())
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Middleware assigning request ids and turning errors and panics into error pages,
+/// or JSON for API requests. Server errors are logged with request id.
+pub struct ErrorPages;
+
+impl<S, B> Transform<S> for ErrorPages
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ErrorPagesMiddleware<S>;
+    type Future = Ready<result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ErrorPagesMiddleware { service })
+    }
+}
+
+pub struct ErrorPagesMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for ErrorPagesMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = result::Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<result::Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let RequestId(id) = RequestId::of(&req);
+        req.extensions_mut().insert(RequestId(id.clone()));
+        let json = wants_json(&req);
+        let described = format!("{} {}", req.method(), req.path());
+        // panicking handler would otherwise drop connection without response
+        let response = AssertUnwindSafe(self.service.call(req)).catch_unwind();
+
+        Box::pin(async move {
+            let mut res = match response.await {
+                Ok(Ok(res)) => {
+                    let rendered = res.response().error().map(|err| {
+                        // not ready yet is expected while background jobs run
+                        if res.status().is_server_error()
+                            && res.status() != StatusCode::SERVICE_UNAVAILABLE
+                        {
+                            log::error!("[{}] {} failed: {}", id, described, err);
+                        }
+                        RenderedError::of(json, err, &id)
+                    });
+                    match rendered {
+                        Some(rendered) => res.into_response(rendered.error_response()),
+                        None => {
+                            res.map_body(|_, body| ResponseBody::Other(Body::from_message(body)))
+                        }
+                    }
+                }
+                Ok(Err(err)) => {
+                    log::error!("[{}] {} failed: {}", id, described, err);
+                    return Err(RenderedError::of(json, &err, &id).into());
+                }
+                Err(panic) => {
+                    log::error!(
+                        "[{}] {} panicked: {}",
+                        id,
+                        described,
+                        panic_message(panic.as_ref())
+                    );
+                    let err = WebError::Internal(anyhow::anyhow!("Handler panicked"));
+                    return Err(RenderedError::new(
+                        json,
+                        err.status_code(),
+                        err.kind(),
+                        &err.message(),
+                        &id,
+                    )
+                    .into());
+                }
+            };
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID), value);
+            }
+            Ok(res)
+        })
+    }
+}
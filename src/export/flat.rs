@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::ExportFilter;
+use crate::reader::{floating_timestamp::to_slack_ts, MessagesReader};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlatFormat {
+    Csv,
+    JsonLines,
+}
+
+impl FromStr for FlatFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(FlatFormat::Csv),
+            "jsonl" | "json-lines" => Ok(FlatFormat::JsonLines),
+            _ => Err(format!("Unknown flat format: {}", s)),
+        }
+    }
+}
+
+/// Single message flattened to one row
+#[derive(Serialize)]
+struct Row<'a> {
+    channel_id: &'a str,
+    channel_name: &'a str,
+    ts: String,
+    thread_ts: Option<String>,
+    user_id: &'a str,
+    user_name: &'a str,
+    subtype: Option<&'a str>,
+    text: &'a str,
+    reaction_count: u32,
+    reactions: String,
+    file_count: usize,
+    edited: bool,
+}
+
+enum RowWriter<W: Write> {
+    Csv(csv::Writer<W>),
+    JsonLines(W),
+}
+
+impl<W: Write> RowWriter<W> {
+    fn new(format: FlatFormat, out: W) -> Self {
+        match format {
+            FlatFormat::Csv => RowWriter::Csv(csv::Writer::from_writer(out)),
+            FlatFormat::JsonLines => RowWriter::JsonLines(out),
+        }
+    }
+
+    fn write(&mut self, row: &Row) -> Result<()> {
+        match self {
+            RowWriter::Csv(writer) => writer.serialize(row)?,
+            RowWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            RowWriter::Csv(writer) => writer.flush()?,
+            RowWriter::JsonLines(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Stream selected messages as rows, only single day is kept in memory at a time
+pub fn write_flat(
+    out: impl Write,
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    format: FlatFormat,
+) -> Result<()> {
+    let mut writer = RowWriter::new(format, out);
+    for channel in filter.channels(reader)? {
+        filter.for_each_message(reader, channel, |_, entry| {
+            writer.write(&Row {
+                channel_id: &channel.id,
+                channel_name: &channel.name,
+                ts: to_slack_ts(&entry.timestamp),
+                thread_ts: entry.thread_ts.as_ref().map(to_slack_ts),
+                user_id: &entry.user_id,
+                user_name: reader
+                    .get_user_info(&entry.user_id)
+                    .map(|user| user.display_name())
+                    .unwrap_or(""),
+                subtype: entry.subtype.as_deref(),
+                text: &entry.text,
+                reaction_count: entry.reactions.iter().map(|reaction| reaction.count).sum(),
+                reactions: entry
+                    .reactions
+                    .iter()
+                    .map(|reaction| format!("{}:{}", reaction.name, reaction.count))
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                file_count: entry.files.len(),
+                edited: entry.edited.is_some(),
+            })
+        })?;
+    }
+    writer.flush()
+}
+
+/// Export to `output` file, `-` writes to stdout
+pub fn export_flat(
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    format: FlatFormat,
+    output: &Path,
+) -> Result<()> {
+    if output == Path::new("-") {
+        write_flat(BufWriter::new(stdout()), reader, filter, format)
+    } else {
+        let file = File::create(output)
+            .with_context(|| format!("Failed to create {}", output.display()))?;
+        write_flat(BufWriter::new(file), reader, filter, format)
+    }
+}
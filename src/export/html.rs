@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{create_dir_all, write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use super::{dates_in_range, for_each_day};
+use crate::{
+    authz::Viewer,
+    reader::{
+        floating_timestamp::to_slack_ts, ChannelInfo, ChannelMessages, Entry, MessagesReader,
+    },
+    ui::{self, layout_context, render_page, user::ChannelActivity, LayoutContext, Links},
+};
+
+#[derive(Serialize)]
+struct SearchContext<'a> {
+    layout: LayoutContext<'a>,
+}
+
+/// Single message in client side search index, keys are kept short as index can be large
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+    /// Channel id
+    c: &'a str,
+    /// Channel name
+    n: &'a str,
+    /// Date
+    d: String,
+    /// Time
+    t: String,
+    /// Slack `ts`, anchor of message in day page
+    s: String,
+    /// User name
+    u: &'a str,
+    /// Text
+    x: String,
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn copy_static(output: &Path) -> Result<()> {
+    for file in ui::static_files() {
+        let path = output.join("static").join(file.path());
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        write_file(&path, file.contents())?;
+    }
+    Ok(())
+}
+
+/// Threads with their parent and at least one reply, messages in order of posting
+fn threads(days: &[(NaiveDate, ChannelMessages)]) -> BTreeMap<DateTime<Utc>, Vec<&Entry>> {
+    let mut threads: BTreeMap<DateTime<Utc>, Vec<&Entry>> = BTreeMap::new();
+    for (_, messages) in days {
+        for msg in &messages.messages {
+            if let Some(thread_ts) = msg.thread_ts {
+                threads.entry(thread_ts).or_default().push(msg);
+            }
+        }
+    }
+    for thread in threads.values_mut() {
+        thread.sort_by_key(|msg| msg.timestamp);
+    }
+    // threads whose parent is outside of export or retention have no page to link from
+    threads.retain(|thread_ts, thread| {
+        thread.len() > 1 && thread.first().map(|parent| parent.timestamp) == Some(*thread_ts)
+    });
+    threads
+}
+
+/// Write day, thread and index pages of channel, returns number of messages by user id
+fn export_channel<'a>(
+    reader: &'a MessagesReader,
+    channel: &'a ChannelInfo,
+    output: &Path,
+    search_index: &mut Vec<SearchEntry<'a>>,
+) -> Result<HashMap<String, usize>> {
+    log::info!("Exporting channel {}", channel.name);
+    let channel_dir = output.join(&channel.id);
+    create_dir_all(&channel_dir)
+        .with_context(|| format!("Failed to create {}", channel_dir.display()))?;
+
+    // replies can be posted days after their parent, so whole channel is loaded first
+    let mut days = Vec::new();
+    let dates = dates_in_range(reader, &channel.id, None, None)?;
+    for_each_day(reader, channel, dates.iter().copied(), |date, messages| {
+        days.push((date, messages));
+        Ok(())
+    })?;
+    let threads = threads(&days);
+    let replies = threads
+        .iter()
+        .map(|(thread_ts, thread)| (*thread_ts, thread.len() - 1))
+        .collect();
+
+    let mut posted: HashMap<String, usize> = HashMap::new();
+    for (date, messages) in &days {
+        write_file(
+            &channel_dir.join(format!("{}.html", date)),
+            ui::view_day::render_static(reader, messages, &replies, Links::static_export(1))?
+                .as_bytes(),
+        )?;
+
+        for msg in &messages.messages {
+            *posted.entry(msg.user_id.clone()).or_default() += 1;
+            search_index.push(SearchEntry {
+                c: &channel.id,
+                n: &channel.name,
+                d: date.to_string(),
+                t: msg.timestamp.format("%H:%M:%S").to_string(),
+                s: to_slack_ts(&msg.timestamp),
+                u: reader
+                    .get_user_info(&msg.user_id)
+                    .map_or("Unknown", |user| user.display_name()),
+                x: msg.text.clone(),
+            });
+
+            if let Some(thread) = threads.get(&msg.timestamp) {
+                write_file(
+                    &channel_dir.join(format!("thread-{}.html", to_slack_ts(&msg.timestamp))),
+                    ui::thread::render_static(
+                        reader,
+                        channel,
+                        *date,
+                        thread,
+                        Links::static_export(1),
+                    )?
+                    .as_bytes(),
+                )?;
+            }
+        }
+    }
+
+    write_file(
+        &channel_dir.join("index.html"),
+        ui::select_date::render_static(reader, &channel.id, dates, Links::static_export(1))?
+            .as_bytes(),
+    )?;
+    Ok(posted)
+}
+
+/// Render every page of the archive into `output` as relatively linked html files
+pub fn export_html(reader: &MessagesReader, output: &Path) -> Result<()> {
+    create_dir_all(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    copy_static(output)?;
+    // every page has them in sidebar
+    reader.compute_summaries();
+
+    write_file(
+        &output.join("index.html"),
+        ui::index::render_static(reader, Links::static_export(0))?.as_bytes(),
+    )?;
+    write_file(
+        &output.join("search.html"),
+        render_page(
+            "search.tera",
+            &SearchContext {
+                layout: layout_context(reader, &Viewer::unrestricted())
+                    .with_links(Links::static_export(0)),
+            },
+        )?
+        .as_bytes(),
+    )?;
+
+    let mut search_index = Vec::new();
+    let mut activity: HashMap<&str, Vec<ChannelActivity<'_>>> = HashMap::new();
+    let mut channels = reader.list_channels();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for channel in channels {
+        for (user_id, messages) in export_channel(reader, channel, output, &mut search_index)? {
+            if let Ok(user) = reader.get_user_info(&user_id) {
+                activity
+                    .entry(&user.id)
+                    .or_default()
+                    .push(ChannelActivity { channel, messages });
+            }
+        }
+    }
+
+    let users_dir = output.join("users");
+    create_dir_all(&users_dir)
+        .with_context(|| format!("Failed to create {}", users_dir.display()))?;
+    for user in reader.list_users() {
+        write_file(
+            &users_dir.join(format!("{}.html", user.id)),
+            ui::user::render_static(
+                reader,
+                user,
+                activity.get(user.id.as_str()).map_or(&[], Vec::as_slice),
+                Links::static_export(1),
+            )?
+            .as_bytes(),
+        )?;
+    }
+
+    // Loaded as a script instead of fetched json so search works from `file://` too
+    let search_index = format!(
+        "window.SEARCH_INDEX = {};\n",
+        serde_json::to_string(&search_index)?
+    );
+    write_file(&output.join("search-index.js"), search_index.as_bytes())?;
+
+    log::info!("Archive exported to {}", output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::test_support::{self, PARENT_TS};
+
+    #[test]
+    fn test_export_links() {
+        let reader = test_support::reader();
+        let output = tempfile::tempdir().unwrap();
+        export_html(&reader, output.path()).unwrap();
+        // tera escapes slashes in attributes, browsers decode them
+        let read = |path: &str| {
+            read_to_string(output.path().join(path))
+                .unwrap()
+                .replace("&#x2F;", "/")
+        };
+
+        let day = read("C1/2020-10-11.html");
+        assert!(day.contains(&format!("href=\"thread-{}.html\"", PARENT_TS)));
+        assert!(day.contains("href=\"../users/U2.html\""));
+        assert!(day.contains("href=\"../C2/index.html\""));
+        assert!(day.contains("href=\"../static/styles.css?v="));
+        // every page has to work from file system
+        assert!(!day.contains("href=\"/"));
+        assert!(!day.contains("/p/"));
+
+        let thread = read(&format!("C1/thread-{}.html", PARENT_TS));
+        assert!(thread.contains("Sure") && thread.contains("Too late"));
+        assert!(thread.contains(&format!("href=\"../C1/2020-10-11.html#{}\"", PARENT_TS)));
+        let user = read("users/U1.html");
+        assert!(user.contains("href=\"../C1/index.html\">#general</a>"));
+        assert!(!read("index.html").contains("href=\"/"));
+
+        let index = read("search-index.js");
+        let json = index
+            .trim_start_matches("window.SEARCH_INDEX = ")
+            .trim_end_matches(";\n");
+        let entries: Vec<serde_json::Value> = serde_json::from_str(json).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert!(entries
+            .iter()
+            .any(|entry| entry["c"] == "C1" && entry["s"] == PARENT_TS && entry["u"] == "Alice"));
+    }
+}
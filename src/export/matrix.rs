@@ -0,0 +1,362 @@
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::ExportFilter;
+use crate::{
+    mrkdwn::{self, Resolver},
+    reader::{floating_timestamp::to_slack_ts, ChannelInfo, Entry, MessagesReader},
+};
+
+/// Sender of events without known Slack author, e.g. topic without creator
+const IMPORT_USER: &str = "slack_import";
+
+#[derive(Serialize)]
+struct Room<'a> {
+    alias: String,
+    name: &'a str,
+    topic: Option<&'a str>,
+    members: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Event {
+    #[serde(rename = "type")]
+    event_type: &'static str,
+    event_id: String,
+    sender: String,
+    origin_server_ts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_key: Option<&'static str>,
+    content: Value,
+}
+
+#[derive(Serialize)]
+struct RoomExport<'a> {
+    room: Room<'a>,
+    events: Vec<Event>,
+}
+
+/// Matrix localparts allow only lowercase letters, digits and `._=-/`
+fn sanitize_localpart(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._=-/".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+struct Ids<'a> {
+    reader: &'a MessagesReader,
+    server_name: &'a str,
+}
+
+impl<'a> Ids<'a> {
+    /// Deterministic Matrix id of Slack user, based on Slack user id as sanitized names
+    /// can collide
+    fn user(&self, user_id: &str) -> String {
+        format!(
+            "@slack_{}:{}",
+            sanitize_localpart(user_id),
+            self.server_name
+        )
+    }
+
+    fn room_alias(&self, channel: &ChannelInfo) -> String {
+        format!(
+            "#slack_{}:{}",
+            sanitize_localpart(&channel.name),
+            self.server_name
+        )
+    }
+
+    fn message(&self, channel: &ChannelInfo, ts: &chrono::DateTime<chrono::Utc>) -> String {
+        format!("$slack_{}_{}", channel.id, to_slack_ts(ts))
+    }
+}
+
+impl<'a> Resolver for Ids<'a> {
+    fn user_name(&self, user_id: &str) -> String {
+        self.reader
+            .get_user_info(user_id)
+            .map_or(user_id, |user| user.display_name())
+            .to_string()
+    }
+
+    fn channel_name(&self, channel_id: &str) -> Option<String> {
+        self.reader
+            .get_channel_name(channel_id)
+            .ok()
+            .map(|name| name.to_string())
+    }
+
+    fn user_url(&self, user_id: &str) -> Option<String> {
+        Some(format!("https://matrix.to/#/{}", self.user(user_id)))
+    }
+}
+
+fn message_events(ids: &Ids, channel: &ChannelInfo, entry: &Entry) -> Vec<Event> {
+    let event_id = ids.message(channel, &entry.timestamp);
+    let origin_server_ts = entry.timestamp.timestamp_millis();
+
+    let mut body = mrkdwn::to_markdown(&entry.text, ids);
+    let mut formatted_body = mrkdwn::to_html(&entry.text, ids);
+    for file in &entry.files {
+        body.push_str(&format!("\n[file: {}]", file.display_name()));
+        formatted_body.push_str(&format!(
+            "<br>[file: {}]",
+            mrkdwn::escape_html(file.display_name())
+        ));
+    }
+    let mut content = json!({
+        "msgtype": "m.text",
+        "body": body,
+        "format": "org.matrix.custom.html",
+        "formatted_body": formatted_body,
+    });
+    if let Some(thread_ts) = entry.thread_ts.filter(|_| entry.is_thread_reply()) {
+        let root = ids.message(channel, &thread_ts);
+        content["m.relates_to"] = json!({
+            "rel_type": "m.thread",
+            "event_id": root,
+            "is_falling_back": true,
+            "m.in_reply_to": { "event_id": root },
+        });
+    }
+
+    let mut events = vec![Event {
+        event_type: "m.room.message",
+        event_id: event_id.clone(),
+        sender: ids.user(&entry.user_id),
+        origin_server_ts,
+        state_key: None,
+        content,
+    }];
+    for reaction in &entry.reactions {
+        for user in &reaction.users {
+            events.push(Event {
+                event_type: "m.reaction",
+                event_id: format!("{}_{}_{}", event_id, reaction.name, user),
+                sender: ids.user(user),
+                origin_server_ts,
+                state_key: None,
+                content: json!({
+                    "m.relates_to": {
+                        "rel_type": "m.annotation",
+                        "event_id": event_id,
+                        "key": format!(":{}:", reaction.name),
+                    }
+                }),
+            });
+        }
+    }
+    events
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, value)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write one `rooms/<channel id>.json` with room events per channel and `users.json` with
+/// mapping of Slack user ids to Matrix ids into `output` directory
+pub fn export_matrix(
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    server_name: &str,
+    output: &Path,
+) -> Result<()> {
+    let ids = Ids {
+        reader,
+        server_name,
+    };
+    let rooms = output.join("rooms");
+    create_dir_all(&rooms).with_context(|| format!("Failed to create {}", rooms.display()))?;
+
+    for channel in filter.channels(reader)? {
+        let mut events = Vec::new();
+        if let Some(topic) = channel
+            .topic
+            .as_ref()
+            .filter(|topic| !topic.value.is_empty())
+        {
+            events.push(Event {
+                event_type: "m.room.topic",
+                event_id: format!("$slack_{}_topic", channel.id),
+                sender: if topic.creator.is_empty() {
+                    format!("@{}:{}", IMPORT_USER, server_name)
+                } else {
+                    ids.user(&topic.creator)
+                },
+                origin_server_ts: topic.last_set * 1000,
+                state_key: Some(""),
+                content: json!({ "topic": topic.value }),
+            });
+        }
+        filter.for_each_message(reader, channel, |_, entry| {
+            events.extend(message_events(&ids, channel, entry));
+            Ok(())
+        })?;
+
+        write_json(
+            &rooms.join(format!("{}.json", channel.id)),
+            &RoomExport {
+                room: Room {
+                    alias: ids.room_alias(channel),
+                    name: &channel.name,
+                    topic: channel.topic.as_ref().map(|topic| topic.value.as_str()),
+                    members: channel
+                        .members
+                        .iter()
+                        .map(|member| ids.user(member))
+                        .collect(),
+                },
+                events,
+            },
+        )?;
+    }
+
+    let users: BTreeMap<_, _> = reader
+        .list_users()
+        .into_iter()
+        .map(|user| (user.id.as_str(), ids.user(&user.id)))
+        .collect();
+    write_json(&output.join("users.json"), &users)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::test_support::{self, PARENT_TS, REPLY_TS};
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_export_matrix() {
+        let reader = test_support::reader();
+        let output = tempfile::tempdir().unwrap();
+        let filter = ExportFilter::default();
+        export_matrix(&reader, &filter, "example.org", output.path()).unwrap();
+
+        let users = read_json(&output.path().join("users.json"));
+        assert_eq!(
+            users,
+            json!({"U1": "@slack_u1:example.org", "U2": "@slack_u2:example.org"})
+        );
+
+        let general = read_json(&output.path().join("rooms/C1.json"));
+        assert_eq!(general["room"]["alias"], "#slack_general:example.org");
+        let events = general["events"].as_array().unwrap();
+        assert_eq!(events.len(), 4);
+        let root = format!("$slack_C1_{}", PARENT_TS);
+        assert_eq!(events[0]["event_id"], root);
+        assert!(events[0]["content"].get("m.relates_to").is_none());
+        assert_eq!(events[1]["event_id"], format!("$slack_C1_{}", REPLY_TS));
+        assert_eq!(events[1]["sender"], "@slack_u2:example.org");
+        let relation = &events[1]["content"]["m.relates_to"];
+        assert_eq!(relation["rel_type"], "m.thread");
+        assert_eq!(relation["event_id"], root);
+        assert_eq!(relation["m.in_reply_to"]["event_id"], root);
+        // reply posted on the next day still belongs to the thread
+        assert_eq!(events[3]["content"]["m.relates_to"]["event_id"], root);
+    }
+
+    #[test]
+    fn test_colliding_names() {
+        let export = tempfile::tempdir().unwrap();
+        test_support::create_export(export.path());
+        test_support::write_json(
+            &export.path().join("users.json"),
+            &json!([
+                {"id": "U1", "name": "John Doe", "profile": {"display_name": "", "real_name": "John"}},
+                {"id": "U2", "name": "john_doe", "profile": {"display_name": "", "real_name": "Johnny"}},
+            ]),
+        );
+        test_support::write_json(
+            &export.path().join("channels.json"),
+            &json!([
+                {"id": "C1", "name": "general", "members": ["U1", "U2"]},
+                {"id": "C2", "name": "users", "members": ["U1"]},
+            ]),
+        );
+        std::fs::rename(export.path().join("random"), export.path().join("users")).unwrap();
+        let reader = MessagesReader::new(export.path().to_path_buf()).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        export_matrix(
+            &reader,
+            &ExportFilter::default(),
+            "example.org",
+            output.path(),
+        )
+        .unwrap();
+
+        let users = read_json(&output.path().join("users.json"));
+        assert_ne!(users["U1"], users["U2"]);
+        // channel named as users mapping doesn't overwrite it
+        assert_eq!(
+            read_json(&output.path().join("rooms/C2.json"))["room"]["name"],
+            "users"
+        );
+    }
+
+    #[test]
+    fn test_reactions() {
+        let reader = test_support::reader();
+        let ids = Ids {
+            reader: &reader,
+            server_name: "example.org",
+        };
+        let channel = reader.get_channel("C1").unwrap();
+        let entry: Entry = serde_json::from_value(json!({
+            "type": "message",
+            "ts": PARENT_TS,
+            "user": "U1",
+            "text": "hi <@U2>",
+            "reactions": [{"name": "tada", "users": ["U1", "U2"], "count": 2}],
+        }))
+        .unwrap();
+
+        let events = message_events(&ids, channel, &entry);
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].content["formatted_body"],
+            "hi <a href=\"https://matrix.to/#/@slack_u2:example.org\">bob</a>"
+        );
+        let reaction = &events[2];
+        assert_eq!(reaction.event_type, "m.reaction");
+        assert_eq!(reaction.sender, "@slack_u2:example.org");
+        assert_eq!(
+            reaction.content["m.relates_to"],
+            json!({
+                "rel_type": "m.annotation",
+                "event_id": format!("$slack_C1_{}", PARENT_TS),
+                "key": ":tada:",
+            })
+        );
+        // same ids on every export, so repeated imports are deduplicated
+        assert_eq!(
+            reaction.event_id,
+            message_events(&ids, channel, &entry)[2].event_id
+        );
+    }
+}
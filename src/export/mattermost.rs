@@ -0,0 +1,554 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{local_file_path, ExportFilter};
+use crate::{
+    mrkdwn::{self, Resolver},
+    reader::{ChannelInfo, ChannelKind, Entry, MessagesReader},
+};
+
+const USERNAME_MAX_LEN: usize = 22;
+
+/// Line of Mattermost bulk import file
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'a> {
+    Version { version: u32 },
+    Team { team: Team<'a> },
+    Channel { channel: Channel<'a> },
+    User { user: User<'a> },
+    Post { post: Post },
+    DirectChannel { direct_channel: DirectChannel },
+    DirectPost { direct_post: Post },
+}
+
+#[derive(Serialize)]
+struct Team<'a> {
+    name: &'a str,
+    display_name: &'a str,
+    #[serde(rename = "type")]
+    team_type: &'static str,
+    allow_open_invite: bool,
+}
+
+#[derive(Serialize)]
+struct Channel<'a> {
+    team: &'a str,
+    name: String,
+    display_name: &'a str,
+    #[serde(rename = "type")]
+    channel_type: &'static str,
+    header: Option<&'a str>,
+    purpose: Option<&'a str>,
+}
+
+/// Group or direct message conversation, identified by its members
+#[derive(Serialize)]
+struct DirectChannel {
+    members: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct User<'a> {
+    username: String,
+    email: String,
+    nickname: &'a str,
+    teams: Vec<UserTeam<'a>>,
+}
+
+#[derive(Serialize)]
+struct UserTeam<'a> {
+    name: &'a str,
+    roles: &'static str,
+    channels: Vec<UserChannel>,
+}
+
+#[derive(Serialize)]
+struct UserChannel {
+    name: String,
+    roles: &'static str,
+}
+
+#[derive(Serialize)]
+struct Post {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    /// Only for direct posts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_members: Option<Vec<String>>,
+    user: String,
+    message: String,
+    create_at: i64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reactions: Vec<PostReaction>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostAttachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replies: Option<Vec<Post>>,
+}
+
+#[derive(Serialize)]
+struct PostReaction {
+    user: String,
+    emoji_name: String,
+    create_at: i64,
+}
+
+#[derive(Serialize)]
+struct PostAttachment {
+    path: String,
+}
+
+/// Mattermost allows lowercase letters, digits and `.-_` in names and requires
+/// them to start with letter
+fn sanitize_name(name: &str, min_len: usize, max_len: usize) -> String {
+    let mut sanitized: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !sanitized.starts_with(|c: char| c.is_ascii_lowercase()) {
+        sanitized.insert(0, 'x');
+    }
+    while sanitized.len() < min_len {
+        sanitized.push('_');
+    }
+    sanitized.truncate(max_len);
+    sanitized
+}
+
+/// Sanitized usernames by user id, made unique with numeric suffix as different Slack
+/// names can sanitize to the same one
+fn unique_usernames<'a>(
+    users: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> HashMap<String, String> {
+    let mut users: Vec<_> = users.into_iter().collect();
+    // taken in the same order every time, so repeated exports assign the same suffixes
+    users.sort_unstable();
+    let mut taken = HashSet::new();
+    let mut usernames = HashMap::new();
+    for (user_id, name) in users {
+        let base = sanitize_name(name, 3, USERNAME_MAX_LEN);
+        let mut username = base.clone();
+        let mut attempt = 1;
+        while taken.contains(&username) {
+            attempt += 1;
+            let suffix = format!("_{}", attempt);
+            username = format!(
+                "{}{}",
+                &base[..base.len().min(USERNAME_MAX_LEN - suffix.len())],
+                suffix
+            );
+        }
+        taken.insert(username.clone());
+        usernames.insert(user_id.to_string(), username);
+    }
+    usernames
+}
+
+struct Names<'a> {
+    reader: &'a MessagesReader,
+    usernames: HashMap<String, String>,
+}
+
+impl<'a> Names<'a> {
+    fn new(reader: &'a MessagesReader) -> Self {
+        let usernames = unique_usernames(
+            reader
+                .list_users()
+                .into_iter()
+                .map(|user| (user.id.as_str(), user.name.as_str())),
+        );
+        Self { reader, usernames }
+    }
+
+    fn user(&self, user_id: &str) -> String {
+        self.usernames
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| sanitize_name(user_id, 3, USERNAME_MAX_LEN))
+    }
+
+    fn members(&self, channel: &ChannelInfo) -> Vec<String> {
+        channel
+            .members
+            .iter()
+            .map(|member| self.user(member))
+            .collect()
+    }
+
+    fn channel(&self, channel: &ChannelInfo) -> String {
+        sanitize_name(&channel.name, 2, 64)
+    }
+}
+
+impl<'a> Resolver for Names<'a> {
+    fn user_name(&self, user_id: &str) -> String {
+        self.user(user_id)
+    }
+
+    fn channel_name(&self, channel_id: &str) -> Option<String> {
+        self.reader
+            .resolve_channel(channel_id)
+            .ok()
+            .map(|channel| self.channel(channel))
+    }
+}
+
+fn convert_post(names: &Names, entry: &Entry, files_dir: Option<&Path>) -> Post {
+    let create_at = entry.timestamp.timestamp_millis();
+    let mut message = mrkdwn::to_markdown(&entry.text, names);
+    let mut attachments = Vec::new();
+    for file in &entry.files {
+        match local_file_path(files_dir, file) {
+            Some(path) => attachments.push(PostAttachment {
+                path: path.to_string_lossy().into_owned(),
+            }),
+            None => {
+                message.push_str(&format!(
+                    "\n[file: {}]{}",
+                    file.display_name(),
+                    file.url_private
+                        .as_ref()
+                        .map(|url| format!("({})", url))
+                        .unwrap_or_default()
+                ));
+            }
+        }
+    }
+    Post {
+        team: None,
+        channel: None,
+        channel_members: None,
+        user: names.user(&entry.user_id),
+        message,
+        create_at,
+        reactions: entry
+            .reactions
+            .iter()
+            .flat_map(|reaction| {
+                reaction.users.iter().map(move |user| PostReaction {
+                    user: names.user(user),
+                    emoji_name: reaction.name.clone(),
+                    create_at,
+                })
+            })
+            .collect(),
+        attachments,
+        replies: None,
+    }
+}
+
+/// Group and direct messages are imported as direct channels, not as team channels
+fn is_direct(channel: &ChannelInfo) -> bool {
+    matches!(channel.kind, ChannelKind::Group | ChannelKind::Direct)
+}
+
+fn write_line(out: &mut impl Write, line: &Line) -> Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Users with their memberships in team channels
+fn write_users(
+    out: &mut impl Write,
+    reader: &MessagesReader,
+    names: &Names,
+    channels: &[&ChannelInfo],
+    team: &str,
+) -> Result<()> {
+    let mut memberships: HashMap<&str, BTreeSet<String>> = HashMap::new();
+    for channel in channels.iter().filter(|channel| !is_direct(channel)) {
+        for member in &channel.members {
+            memberships
+                .entry(member)
+                .or_default()
+                .insert(names.channel(channel));
+        }
+    }
+    let mut users = reader.list_users();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    for user in users {
+        let username = names.user(&user.id);
+        write_line(
+            out,
+            &Line::User {
+                user: User {
+                    email: user
+                        .profile
+                        .email
+                        .clone()
+                        .unwrap_or_else(|| format!("{}@slack.invalid", username)),
+                    username,
+                    nickname: user.display_name(),
+                    teams: vec![UserTeam {
+                        name: team,
+                        roles: "team_user",
+                        channels: memberships
+                            .remove(user.id.as_str())
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|name| UserChannel {
+                                name,
+                                roles: "channel_user",
+                            })
+                            .collect(),
+                    }],
+                },
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Write Mattermost bulk import JSONL, thread replies are attached to their parent post
+pub fn write_mattermost(
+    out: &mut impl Write,
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    team: &str,
+    team_display_name: &str,
+    files_dir: Option<&Path>,
+) -> Result<()> {
+    let names = Names::new(reader);
+    let channels = filter.channels(reader)?;
+
+    write_line(out, &Line::Version { version: 1 })?;
+    write_line(
+        out,
+        &Line::Team {
+            team: Team {
+                name: team,
+                display_name: team_display_name,
+                team_type: "I",
+                allow_open_invite: false,
+            },
+        },
+    )?;
+    for channel in &channels {
+        let channel_type = match channel.kind {
+            ChannelKind::Public => "O",
+            ChannelKind::Private => "P",
+            // users have to exist before direct channels are imported
+            ChannelKind::Group | ChannelKind::Direct => continue,
+        };
+        write_line(
+            out,
+            &Line::Channel {
+                channel: Channel {
+                    team,
+                    name: names.channel(channel),
+                    display_name: &channel.name,
+                    channel_type,
+                    header: channel.topic.as_ref().map(|topic| topic.value.as_str()),
+                    purpose: channel
+                        .purpose
+                        .as_ref()
+                        .map(|purpose| purpose.value.as_str()),
+                },
+            },
+        )?;
+    }
+
+    write_users(out, reader, &names, &channels, team)?;
+    for channel in channels.iter().filter(|channel| is_direct(channel)) {
+        write_line(
+            out,
+            &Line::DirectChannel {
+                direct_channel: DirectChannel {
+                    members: names.members(channel),
+                },
+            },
+        )?;
+    }
+
+    for channel in &channels {
+        // whole channel is collected, as replies can be posted days after parent;
+        // keyed by full timestamp as several messages can share the same millisecond
+        let mut posts: BTreeMap<DateTime<Utc>, Post> = BTreeMap::new();
+        filter.for_each_message(reader, channel, |_, entry| {
+            let post = convert_post(&names, entry, files_dir);
+            let parent = entry
+                .thread_ts
+                .filter(|_| entry.is_thread_reply())
+                .and_then(|thread_ts| posts.get_mut(&thread_ts));
+            match parent {
+                Some(parent) => parent.replies.get_or_insert_with(Vec::new).push(post),
+                None => {
+                    posts.insert(entry.timestamp, post);
+                }
+            }
+            Ok(())
+        })?;
+        for (_, mut post) in posts {
+            if is_direct(channel) {
+                post.channel_members = Some(names.members(channel));
+                write_line(out, &Line::DirectPost { direct_post: post })?;
+            } else {
+                post.team = Some(team.to_string());
+                post.channel = Some(names.channel(channel));
+                write_line(out, &Line::Post { post })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn export_mattermost(
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    team: &str,
+    team_display_name: Option<&str>,
+    files_dir: Option<&Path>,
+    output: &Path,
+) -> Result<()> {
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut out = BufWriter::new(file);
+    write_mattermost(
+        &mut out,
+        reader,
+        filter,
+        &sanitize_name(team, 2, 64),
+        team_display_name.unwrap_or(team),
+        files_dir,
+    )?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_support::{message, write_json};
+
+    #[test]
+    fn test_unique_usernames() {
+        let usernames = unique_usernames(vec![
+            ("U1", "John Doe"),
+            ("U2", "john_doe"),
+            ("U3", "a_very_long_name_of_someone"),
+            ("U4", "a very long name of someone else"),
+        ]);
+        assert_eq!(usernames["U1"], "john_doe");
+        assert_eq!(usernames["U2"], "john_doe_2");
+        assert_eq!(usernames["U3"], "a_very_long_name_of_so");
+        assert_eq!(usernames["U4"], "a_very_long_name_of__2");
+    }
+
+    #[test]
+    fn test_conversation_kinds() {
+        const THREAD_TS: &str = "1602403200.000200";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        write_json(
+            &path.join("channels.json"),
+            &json!([{"id": "C1", "name": "general", "members": ["U1", "U2"]}]),
+        );
+        write_json(
+            &path.join("groups.json"),
+            &json!([{"id": "G1", "name": "secret", "members": ["U1"]}]),
+        );
+        write_json(
+            &path.join("mpims.json"),
+            &json!([{"id": "M1", "name": "mpdm-alice--bob-1", "members": ["U1", "U2"]}]),
+        );
+        write_json(
+            &path.join("dms.json"),
+            &json!([{"id": "D1", "members": ["U1", "U2"]}]),
+        );
+        write_json(
+            &path.join("users.json"),
+            &json!([
+                {"id": "U1", "name": "John Doe", "profile": {"display_name": "", "real_name": "John"}},
+                {"id": "U2", "name": "john_doe", "profile": {"display_name": "", "real_name": "John"}},
+            ]),
+        );
+        // same millisecond as first message, both have to be kept
+        write_json(
+            &path.join("general/2020-10-11.json"),
+            &json!([
+                message("1602403200.000100", "U1", "first", None),
+                message(THREAD_TS, "U2", "second", Some(THREAD_TS)),
+                message("1602403260.000100", "U1", "reply", Some(THREAD_TS)),
+            ]),
+        );
+        for folder in &["secret", "mpdm-alice--bob-1", "D1"] {
+            write_json(
+                &path.join(folder).join("2020-10-11.json"),
+                &json!([message("1602403200.000100", "U2", folder, None)]),
+            );
+        }
+        let reader = MessagesReader::new(path.to_path_buf()).unwrap();
+
+        let mut out = Vec::new();
+        let filter = ExportFilter::default();
+        write_mattermost(&mut out, &reader, &filter, "team", "Team", None).unwrap();
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let of_type = |kind: &str| -> Vec<&Value> {
+            lines.iter().filter(|line| line["type"] == kind).collect()
+        };
+
+        let channels = of_type("channel");
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0]["channel"]["name"], "general");
+        assert_eq!(channels[0]["channel"]["type"], "O");
+        assert_eq!(channels[1]["channel"]["name"], "secret");
+        assert_eq!(channels[1]["channel"]["type"], "P");
+
+        let direct_channels = of_type("direct_channel");
+        assert_eq!(direct_channels.len(), 2);
+        for direct_channel in direct_channels {
+            assert_eq!(
+                direct_channel["direct_channel"]["members"],
+                json!(["john_doe", "john_doe_2"])
+            );
+        }
+
+        let posts = of_type("post");
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts[0]["post"]["message"], "first");
+        assert_eq!(posts[1]["post"]["message"], "second");
+        assert_eq!(posts[1]["post"]["user"], "john_doe_2");
+        assert_eq!(posts[1]["post"]["replies"][0]["message"], "reply");
+        assert_eq!(posts[2]["post"]["channel"], "secret");
+
+        let direct_posts = of_type("direct_post");
+        assert_eq!(direct_posts.len(), 2);
+        assert_eq!(direct_posts[0]["direct_post"]["message"], "D1");
+        assert_eq!(
+            direct_posts[0]["direct_post"]["channel_members"],
+            json!(["john_doe", "john_doe_2"])
+        );
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("John Doe", 3, 22), "john_doe");
+        assert_eq!(sanitize_name("1st", 3, 22), "x1st");
+        assert_eq!(sanitize_name("a", 3, 22), "a__");
+    }
+}
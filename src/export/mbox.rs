@@ -0,0 +1,304 @@
+use std::{
+    fs::{read, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use super::{local_file_path, ExportFilter};
+use crate::reader::{floating_timestamp::to_slack_ts, ChannelInfo, Entry, MessagesReader};
+
+const MAIL_DOMAIN: &str = "slack.invalid";
+
+fn message_id(channel: &ChannelInfo, ts: &DateTime<Utc>) -> String {
+    format!("<{}.{}@{}>", to_slack_ts(ts), channel.id, MAIL_DOMAIN)
+}
+
+/// RFC 2047 encoded word for header values that are not plain ASCII
+fn encode_header(value: &str) -> String {
+    if is_plain_ascii(value) {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(value))
+    }
+}
+
+fn is_plain_ascii(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii() && !c.is_ascii_control())
+}
+
+/// Quoted string with quotes and backslashes escaped
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Display name of address, encoded words can't be used inside quotes
+fn display_name(name: &str) -> String {
+    if is_plain_ascii(name) {
+        quote(name)
+    } else {
+        encode_header(name)
+    }
+}
+
+/// `filename` parameter, non ASCII names are encoded as in RFC 2231
+fn filename_param(name: &str) -> String {
+    if is_plain_ascii(name) {
+        format!("filename={}", quote(name))
+    } else {
+        let encoded: String = name
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                    (b as char).to_string()
+                } else {
+                    format!("%{:02X}", b)
+                }
+            })
+            .collect();
+        format!("filename*=UTF-8''{}", encoded)
+    }
+}
+
+/// Multipart boundary which does not appear in body, derived from content so repeated
+/// exports are the same
+fn boundary(body: &str, attachments: &[Attachment]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(body);
+    for attachment in attachments {
+        hasher.update(&attachment.content);
+    }
+    let hash = hasher.finalize();
+    let base: String = hash[..12].iter().map(|b| format!("{:02x}", b)).collect();
+    let mut boundary = format!("=_slack-{}", base);
+    let mut attempt = 0;
+    while body.contains(&boundary) {
+        attempt += 1;
+        boundary = format!("=_slack-{}-{}", base, attempt);
+    }
+    boundary
+}
+
+/// Escape body lines that could be confused with message separator (mboxrd)
+fn escape_from_lines(body: &str) -> String {
+    let mut escaped = String::with_capacity(body.len());
+    for line in body.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            escaped.push('>');
+        }
+        escaped.push_str(line);
+        escaped.push('\n');
+    }
+    escaped
+}
+
+fn subject(channel: &ChannelInfo, entry: &Entry) -> String {
+    let first_line: String = entry
+        .text
+        .lines()
+        .next()
+        .unwrap_or("")
+        .chars()
+        .take(60)
+        .collect();
+    let prefix = if entry.is_thread_reply() { "Re: " } else { "" };
+    format!("{}[#{}] {}", prefix, channel.name, first_line)
+}
+
+struct Attachment {
+    name: String,
+    mimetype: String,
+    content: Vec<u8>,
+}
+
+fn write_message(
+    out: &mut impl Write,
+    reader: &MessagesReader,
+    channel: &ChannelInfo,
+    entry: &Entry,
+    files_dir: Option<&Path>,
+) -> Result<()> {
+    let user = reader.get_user_info(&entry.user_id).ok();
+    let name = user.map_or(entry.user_id.as_str(), |user| user.display_name());
+    let email = user
+        .and_then(|user| user.profile.email.clone())
+        .unwrap_or_else(|| format!("{}@{}", entry.user_id, MAIL_DOMAIN));
+
+    let mut body = entry.text.clone();
+    body.push('\n');
+    let mut attachments = Vec::new();
+    for file in &entry.files {
+        match local_file_path(files_dir, file) {
+            Some(path) => attachments.push(Attachment {
+                name: file.name.clone().unwrap_or_else(|| file.id.clone()),
+                mimetype: file
+                    .mimetype
+                    .clone()
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                content: read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            }),
+            None => {
+                body.push_str(&format!(
+                    "\n[file: {}{}]",
+                    file.display_name(),
+                    file.url_private
+                        .as_ref()
+                        .map(|url| format!(" {}", url))
+                        .unwrap_or_default()
+                ));
+            }
+        }
+    }
+    if !entry.reactions.is_empty() {
+        body.push_str("\n[reactions:");
+        for reaction in &entry.reactions {
+            body.push_str(&format!(" :{}: {}", reaction.name, reaction.count));
+        }
+        body.push(']');
+    }
+
+    writeln!(
+        out,
+        "From {} {}",
+        email,
+        entry.timestamp.format("%a %b %e %H:%M:%S %Y")
+    )?;
+    writeln!(out, "From: {} <{}>", display_name(name), email)?;
+    writeln!(
+        out,
+        "To: {} <{}@{}>",
+        display_name(&format!("#{}", channel.name)),
+        channel.id,
+        MAIL_DOMAIN
+    )?;
+    writeln!(out, "Date: {}", entry.timestamp.to_rfc2822())?;
+    writeln!(out, "Subject: {}", encode_header(&subject(channel, entry)))?;
+    writeln!(out, "Message-ID: {}", message_id(channel, &entry.timestamp))?;
+    if let Some(thread_ts) = entry.thread_ts.filter(|_| entry.is_thread_reply()) {
+        let parent = message_id(channel, &thread_ts);
+        writeln!(out, "In-Reply-To: {}", parent)?;
+        writeln!(out, "References: {}", parent)?;
+    }
+    writeln!(out, "X-Slack-Channel: {}", channel.id)?;
+    writeln!(out, "X-Slack-User: {}", entry.user_id)?;
+    writeln!(out, "MIME-Version: 1.0")?;
+
+    if attachments.is_empty() {
+        writeln!(out, "Content-Type: text/plain; charset=utf-8")?;
+        writeln!(out, "Content-Transfer-Encoding: 8bit")?;
+        writeln!(out)?;
+        write!(out, "{}", escape_from_lines(&body))?;
+    } else {
+        let boundary = boundary(&body, &attachments);
+        writeln!(
+            out,
+            "Content-Type: multipart/mixed; boundary=\"{}\"",
+            boundary
+        )?;
+        writeln!(out)?;
+        writeln!(out, "--{}", boundary)?;
+        writeln!(out, "Content-Type: text/plain; charset=utf-8")?;
+        writeln!(out, "Content-Transfer-Encoding: 8bit")?;
+        writeln!(out)?;
+        write!(out, "{}", escape_from_lines(&body))?;
+        for attachment in attachments {
+            writeln!(out, "--{}", boundary)?;
+            writeln!(out, "Content-Type: {}", attachment.mimetype)?;
+            writeln!(
+                out,
+                "Content-Disposition: attachment; {}",
+                filename_param(&attachment.name)
+            )?;
+            writeln!(out, "Content-Transfer-Encoding: base64")?;
+            writeln!(out)?;
+            let encoded = base64::encode(&attachment.content);
+            for chunk in encoded.as_bytes().chunks(76) {
+                out.write_all(chunk)?;
+                writeln!(out)?;
+            }
+        }
+        writeln!(out, "--{}--", boundary)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write messages selected by `filter` as one mbox email each
+///
+/// Files are embedded when found in `files_dir` as `<file id>/<file name>`,
+/// otherwise they are only referenced in message body.
+pub fn write_mbox(
+    out: &mut impl Write,
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    files_dir: Option<&Path>,
+) -> Result<()> {
+    for channel in filter.channels(reader)? {
+        filter.for_each_message(reader, channel, |_, entry| {
+            write_message(out, reader, channel, entry, files_dir)
+        })?;
+    }
+    Ok(())
+}
+
+pub fn export_mbox(
+    reader: &MessagesReader,
+    filter: &ExportFilter,
+    files_dir: Option<&Path>,
+    output: &Path,
+) -> Result<()> {
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut out = BufWriter::new(file);
+    write_mbox(&mut out, reader, filter, files_dir)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_escape_from_lines() {
+        let escaped = super::escape_from_lines("From here\n>From there\nnot From\n");
+        assert_eq!(escaped, ">From here\n>>From there\nnot From\n");
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(super::encode_header("plain"), "plain");
+        assert_eq!(
+            super::encode_header("zażółć"),
+            "=?UTF-8?B?emHFvMOzxYLEhw==?="
+        );
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(
+            super::display_name(r#"Bob "B" \ Co"#),
+            r#""Bob \"B\" \\ Co""#
+        );
+        assert_eq!(
+            super::display_name("zażółć"),
+            "=?UTF-8?B?emHFvMOzxYLEhw==?="
+        );
+        assert_eq!(super::filename_param("a b.txt"), r#"filename="a b.txt""#);
+        assert_eq!(
+            super::filename_param("ł.txt"),
+            "filename*=UTF-8''%C5%82.txt"
+        );
+    }
+
+    #[test]
+    fn test_boundary_not_in_body() {
+        let first = super::boundary("text\n", &[]);
+        let body = format!("text\n--{}\n", first);
+        let boundary = super::boundary(&body, &[]);
+        assert!(!body.contains(&boundary));
+        assert_eq!(boundary, super::boundary(&body, &[]));
+    }
+}
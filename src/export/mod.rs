@@ -0,0 +1,124 @@
+pub mod flat;
+pub mod html;
+pub mod matrix;
+pub mod mattermost;
+pub mod mbox;
+pub mod text;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use structopt::StructOpt;
+
+use crate::reader::{ChannelInfo, ChannelMessages, Entry, MessageFile, MessagesReader};
+
+/// Downloaded copy of a file, stored as `<files dir>/<file id>/<file name>`
+pub fn local_file_path(files_dir: Option<&Path>, file: &MessageFile) -> Option<PathBuf> {
+    files_dir
+        .zip(file.name.as_ref())
+        .map(|(dir, name)| dir.join(&file.id).join(name))
+        .filter(|path| path.is_file())
+}
+
+/// Sorted dates of channel history, limited to inclusive range when bounds are given
+pub fn dates_in_range(
+    reader: &MessagesReader,
+    channel_id: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<Vec<NaiveDate>> {
+    let mut dates: Vec<_> = reader
+        .list_dates(channel_id)?
+        .into_iter()
+        .filter(|date| from.map_or(true, |from| *date >= from))
+        .filter(|date| to.map_or(true, |to| *date <= to))
+        .collect();
+    dates.sort();
+    Ok(dates)
+}
+
+/// Load given days of channel in order, days that fail to load are skipped. Days are
+/// read past day cache, as each of them is visited only once.
+pub fn for_each_day(
+    reader: &MessagesReader,
+    channel: &ChannelInfo,
+    dates: impl IntoIterator<Item = NaiveDate>,
+    mut f: impl FnMut(NaiveDate, ChannelMessages) -> Result<()>,
+) -> Result<()> {
+    for date in dates {
+        match reader.channel_messages_uncached(&channel.id, &date) {
+            Ok(messages) => f(date, messages)?,
+            Err(err) => log::warn!("Skipping {} for day {}: {:#}", channel.name, date, err),
+        }
+    }
+    Ok(())
+}
+
+/// Selection of messages shared by exporters
+#[derive(StructOpt, Debug, Default)]
+pub struct ExportFilter {
+    /// Channel id or name, can be repeated, all channels are exported when omitted
+    #[structopt(long = "channel", number_of_values = 1)]
+    pub channels: Vec<String>,
+    /// User id or name, can be repeated, messages of all users are exported when omitted
+    #[structopt(long = "user", number_of_values = 1)]
+    pub users: Vec<String>,
+    /// First day to export
+    #[structopt(long)]
+    pub from: Option<NaiveDate>,
+    /// Last day to export
+    #[structopt(long)]
+    pub to: Option<NaiveDate>,
+}
+
+impl ExportFilter {
+    /// Selected channels sorted by name
+    pub fn channels<'a>(&self, reader: &'a MessagesReader) -> Result<Vec<&'a ChannelInfo>> {
+        let mut channels = if self.channels.is_empty() {
+            reader.list_channels()
+        } else {
+            self.channels
+                .iter()
+                .map(|channel| reader.resolve_channel(channel))
+                .collect::<Result<_>>()?
+        };
+        channels.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(channels)
+    }
+
+    pub fn dates(&self, reader: &MessagesReader, channel_id: &str) -> Result<Vec<NaiveDate>> {
+        dates_in_range(reader, channel_id, self.from, self.to)
+    }
+
+    /// Selected messages of channel, in order of days
+    pub fn for_each_message(
+        &self,
+        reader: &MessagesReader,
+        channel: &ChannelInfo,
+        mut f: impl FnMut(NaiveDate, &Entry) -> Result<()>,
+    ) -> Result<()> {
+        for_each_day(
+            reader,
+            channel,
+            self.dates(reader, &channel.id)?,
+            |date, messages| {
+                messages
+                    .messages
+                    .iter()
+                    .filter(|entry| self.matches(reader, entry))
+                    .try_for_each(|entry| f(date, entry))
+            },
+        )
+    }
+
+    pub fn matches(&self, reader: &MessagesReader, entry: &Entry) -> bool {
+        self.users.is_empty()
+            || self.users.iter().any(|user| {
+                *user == entry.user_id
+                    || reader
+                        .get_user_info(&entry.user_id)
+                        .map_or(false, |info| info.name == *user)
+            })
+    }
+}
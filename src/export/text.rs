@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{create_dir_all, write},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::{dates_in_range, for_each_day};
+use crate::reader::{ChannelMessages, Entry, MessagesReader};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextFormat {
+    Markdown,
+    Plain,
+}
+
+impl TextFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            TextFormat::Markdown => "md",
+            TextFormat::Plain => "txt",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TextFormat::Markdown => "text/markdown; charset=utf-8",
+            TextFormat::Plain => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for TextFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(TextFormat::Markdown),
+            "plain" | "text" | "txt" => Ok(TextFormat::Plain),
+            _ => Err(format!("Unknown text format: {}", s)),
+        }
+    }
+}
+
+fn user_name<'a>(reader: &'a MessagesReader, user_id: &'a str) -> &'a str {
+    reader
+        .get_user_info(user_id)
+        .map(|user| user.display_name())
+        .unwrap_or(user_id)
+}
+
+/// Append `text` to `out` with every line prefixed by `prefix`
+fn push_lines(out: &mut String, prefix: &str, text: &str) {
+    for line in text.lines() {
+        out.push_str(prefix);
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn render_entry(
+    out: &mut String,
+    reader: &MessagesReader,
+    entry: &Entry,
+    format: TextFormat,
+    nested: bool,
+) {
+    let time = entry.timestamp.format("%H:%M:%S");
+    let name = user_name(reader, &entry.user_id);
+    let mut body = String::new();
+    match format {
+        TextFormat::Markdown => {
+            let _ = writeln!(body, "**{}** _{}_", name, time);
+            if let Some(parent) = entry
+                .thread_ts
+                .filter(|_| entry.is_thread_reply() && !nested)
+            {
+                let _ = writeln!(
+                    body,
+                    "_in thread from {}_",
+                    parent.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            let _ = writeln!(body, "{}", entry.text);
+            for file in &entry.files {
+                let _ = writeln!(body, "- file: `{}`", file.display_name());
+            }
+            if !entry.reactions.is_empty() {
+                let _ = writeln!(body, "- reactions: {}", reactions_summary(entry));
+            }
+        }
+        TextFormat::Plain => {
+            let _ = write!(body, "[{}] {}: ", time, name);
+            if let Some(parent) = entry
+                .thread_ts
+                .filter(|_| entry.is_thread_reply() && !nested)
+            {
+                let _ = write!(
+                    body,
+                    "(in thread from {}) ",
+                    parent.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            let _ = writeln!(body, "{}", entry.text);
+            for file in &entry.files {
+                let _ = writeln!(body, "    file: {}", file.display_name());
+            }
+            if !entry.reactions.is_empty() {
+                let _ = writeln!(body, "    reactions: {}", reactions_summary(entry));
+            }
+        }
+    }
+
+    let prefix = match (nested, format) {
+        (false, _) => "",
+        (true, TextFormat::Markdown) => "> ",
+        (true, TextFormat::Plain) => "    | ",
+    };
+    push_lines(out, prefix, &body);
+    if format == TextFormat::Markdown {
+        out.push_str(prefix.trim_end());
+        out.push('\n');
+    }
+}
+
+fn reactions_summary(entry: &Entry) -> String {
+    entry
+        .reactions
+        .iter()
+        .map(|reaction| format!(":{}: {}", reaction.name, reaction.count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Render single day of channel history, thread replies are nested under their parent
+/// when it was posted the same day
+pub fn render_day(
+    reader: &MessagesReader,
+    channel_name: &str,
+    date: NaiveDate,
+    messages: &ChannelMessages,
+    format: TextFormat,
+) -> String {
+    let mut replies: HashMap<DateTime<Utc>, Vec<&Entry>> = HashMap::new();
+    for entry in &messages.messages {
+        if let Some(thread_ts) = entry.thread_ts.filter(|_| entry.is_thread_reply()) {
+            replies.entry(thread_ts).or_default().push(entry);
+        }
+    }
+    let parents: Vec<_> = messages
+        .messages
+        .iter()
+        .filter(|entry| !entry.is_thread_reply())
+        .map(|entry| entry.timestamp)
+        .collect();
+
+    let mut out = String::new();
+    match format {
+        TextFormat::Markdown => {
+            let _ = writeln!(out, "# #{} {}\n", channel_name, date);
+        }
+        TextFormat::Plain => {
+            let title = format!("#{} {}", channel_name, date);
+            let _ = writeln!(out, "{}\n{}\n", title, "=".repeat(title.chars().count()));
+        }
+    }
+
+    for entry in &messages.messages {
+        let orphan = entry
+            .thread_ts
+            .filter(|_| entry.is_thread_reply())
+            .map_or(true, |thread_ts| !parents.contains(&thread_ts));
+        if !orphan {
+            continue;
+        }
+        render_entry(&mut out, reader, entry, format, false);
+        if !entry.is_thread_reply() {
+            for reply in replies.get(&entry.timestamp).into_iter().flatten() {
+                render_entry(&mut out, reader, reply, format, true);
+            }
+        }
+        if format == TextFormat::Plain {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Render each day of channel history in range, days that fail to load are skipped
+pub fn render_days(
+    reader: &MessagesReader,
+    channel_id: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: TextFormat,
+) -> Result<Vec<(NaiveDate, String)>> {
+    let channel = reader.get_channel(channel_id)?;
+    let mut days = Vec::new();
+    let dates = dates_in_range(reader, channel_id, from, to)?;
+    for_each_day(reader, channel, dates, |date, messages| {
+        days.push((
+            date,
+            render_day(reader, &channel.name, date, &messages, format),
+        ));
+        Ok(())
+    })?;
+    Ok(days)
+}
+
+/// Whole range as single document
+pub fn render_document(
+    reader: &MessagesReader,
+    channel_id: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: TextFormat,
+) -> Result<String> {
+    Ok(render_days(reader, channel_id, from, to, format)?
+        .into_iter()
+        .map(|(_, day)| day)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Write channel history to `output`, either as one document or as directory with file per day
+pub fn export_text(
+    reader: &MessagesReader,
+    channel: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: TextFormat,
+    split_days: bool,
+    output: &Path,
+) -> Result<()> {
+    let channel_id = reader.resolve_channel(channel)?.id.clone();
+    if split_days {
+        create_dir_all(output).with_context(|| format!("Failed to create {}", output.display()))?;
+        for (date, day) in render_days(reader, &channel_id, from, to, format)? {
+            let path = output.join(format!("{}.{}", date, format.extension()));
+            write(&path, day).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    } else {
+        let document = render_document(reader, &channel_id, from, to, format)?;
+        write(output, document).with_context(|| format!("Failed to write {}", output.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, message, LATE_REPLY_TS, PARENT_TS, REPLY_TS};
+
+    fn day(reader: &MessagesReader, date: &str) -> (NaiveDate, Arc<ChannelMessages>) {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        let messages = reader.channel_messages_parse("C1", &date).unwrap();
+        (date, messages)
+    }
+
+    #[test]
+    fn test_render_day_markdown() {
+        let reader = test_support::reader();
+        let (date, messages) = day(&reader, "2020-10-11");
+        assert_eq!(
+            render_day(&reader, "general", date, &messages, TextFormat::Markdown),
+            "# #general 2020-10-11\n\n\
+             **Alice** _08:00:00_\nLunch?\n\n\
+             > **bob** _08:01:00_\n> Sure\n>\n\
+             **bob** _09:00:00_\nAnyone here?\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_day_plain() {
+        let reader = test_support::reader();
+        let (date, messages) = day(&reader, "2020-10-11");
+        assert_eq!(
+            render_day(&reader, "general", date, &messages, TextFormat::Plain),
+            "#general 2020-10-11\n===================\n\n\
+             [08:00:00] Alice: Lunch?\n    | [08:01:00] bob: Sure\n\n\
+             [09:00:00] bob: Anyone here?\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_day_orphan_reply() {
+        let reader = test_support::reader();
+        let (date, messages) = day(&reader, "2020-10-12");
+        let markdown = render_day(&reader, "general", date, &messages, TextFormat::Markdown);
+        assert!(markdown.contains("_in thread from 2020-10-11 08:00:00_\nToo late\n"));
+        assert!(!markdown.contains("> "));
+        let plain = render_day(&reader, "general", date, &messages, TextFormat::Plain);
+        assert!(plain.contains("[08:00:00] Alice: (in thread from 2020-10-11 08:00:00) Too late\n"));
+    }
+
+    #[test]
+    fn test_render_day_files_and_reactions() {
+        let reader = test_support::reader();
+        let mut parent = message(PARENT_TS, "U1", "Lunch?", Some(PARENT_TS));
+        parent["files"] = json!([{"id": "F1", "name": "menu.pdf"}]);
+        let mut reply = message(REPLY_TS, "U2", "Sure", Some(PARENT_TS));
+        reply["reactions"] = json!([
+            {"name": "+1", "users": ["U1"], "count": 1},
+            {"name": "tada", "users": ["U1", "U2"], "count": 2},
+        ]);
+        let late = message(LATE_REPLY_TS, "U1", "Too late", None);
+        let messages: ChannelMessages =
+            serde_json::from_value(json!([parent, reply, late])).unwrap();
+        let date = NaiveDate::from_ymd(2020, 10, 11);
+
+        let markdown = render_day(&reader, "general", date, &messages, TextFormat::Markdown);
+        assert!(markdown.contains("Lunch?\n- file: `menu.pdf`\n"));
+        assert!(markdown.contains("> Sure\n> - reactions: :+1: 1, :tada: 2\n"));
+        let plain = render_day(&reader, "general", date, &messages, TextFormat::Plain);
+        assert!(plain.contains("Lunch?\n    file: menu.pdf\n"));
+        assert!(plain.contains("bob: Sure\n    |     reactions: :+1: 1, :tada: 2\n"));
+    }
+}
@@ -15,22 +15,51 @@
     clippy::cast_lossless, // disable for now, maybe re-enable later
 )]
 
+mod audit;
+mod auth;
+mod authz;
+mod cli;
+mod config;
+mod drift;
 mod error;
+mod export;
+mod metrics;
+mod mrkdwn;
 mod reader;
+mod reload;
+mod stats;
 mod ui;
+mod validate;
 mod simple_cache;
+#[cfg(test)]
+mod test_support;
 
-use actix_web::{get, http::StatusCode, web, App, HttpServer};
+use actix_web::{get, middleware::Compress, web, App, HttpServer};
+use cli::{Command, Opts};
 use error::{Result, WebError};
 use lazy_static::lazy_static;
 use reader::MessagesReader;
-use std::{sync::Arc, path::Path};
+use std::io::BufRead;
+use structopt::StructOpt;
 
 lazy_static! {
-    pub static ref READER: Arc<MessagesReader> = Arc::new(MessagesReader::new(Path::new("./data").into()));
+    pub static ref READER: reload::SharedReader = reload::SharedReader::new(load_reader().unwrap());
 }
 
-pub type DataMessagesReader = web::Data<Arc<MessagesReader>>;
+/// Reader for configured export, with redaction and retention applied
+fn load_reader() -> anyhow::Result<MessagesReader> {
+    let config = config::config();
+    let reader = match &config.index_path {
+        Some(index_path) => MessagesReader::from_index(index_path)?,
+        None => MessagesReader::new(config.data_path.clone())?,
+    };
+    Ok(reader
+        .with_redaction(&config.redaction)?
+        .with_retention(&config.retention)
+        .with_day_cache(config.day_cache_bytes))
+}
+
+pub type DataMessagesReader = reload::CurrentReader;
 
 fn setup_logger() -> Result<(), fern::InitError> {
     fern::Dispatch::new()
@@ -44,32 +73,149 @@ fn setup_logger() -> Result<(), fern::InitError> {
             ))
         })
         .level(log::LevelFilter::Debug)
-        .chain(std::io::stdout())
+        .chain(std::io::stderr())
         .apply()?;
     Ok(())
 }
 
 #[get("/test/{chanel}")]
-async fn list_dates(reader: DataMessagesReader, path: web::Path<(String,)>) -> Result<String> {
+async fn list_dates(
+    reader: DataMessagesReader,
+    viewer: authz::Viewer,
+    path: web::Path<(String,)>,
+) -> Result<String> {
     let channel = path.into_inner().0;
+    if !viewer.access.can_view_id(&reader, &channel) {
+        return Err(WebError::ChannelNotFound);
+    }
     let dates = {
         reader.list_dates(&channel)
     }
-    .map_err(|_| WebError::new(StatusCode::NOT_FOUND, "Channel not found"))?;
+    .map_err(|_| WebError::ChannelNotFound)?;
 
     Ok(serde_json::to_string(&dates).unwrap())
 }
 
+fn main() -> anyhow::Result<()> {
+    setup_logger()?;
+    let opts = Opts::from_args();
+    config::init(&opts.config)?;
+    match opts.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            audit::init()?;
+            // fail on start instead of on first request when export is broken
+            lazy_static::initialize(&READER);
+            stats::start(READER.current());
+            reload::watch()?;
+            serve()?
+        }
+        Command::HashPassword => {
+            eprintln!("Enter password:");
+            let mut password = String::new();
+            std::io::stdin().lock().read_line(&mut password)?;
+            println!("{}", auth::hash_password(password.trim_end_matches(&['\r', '\n'][..]))?);
+        }
+        Command::ExportHtml { output } => export::html::export_html(&READER.current(), &output)?,
+        Command::ExportText {
+            channel,
+            from,
+            to,
+            format,
+            split_days,
+            output,
+        } => export::text::export_text(&READER.current(), &channel, from, to, format, split_days, &output)?,
+        Command::ExportMbox {
+            filter,
+            files_dir,
+            output,
+        } => export::mbox::export_mbox(&READER.current(), &filter, files_dir.as_deref(), &output)?,
+        Command::ExportFlat {
+            filter,
+            format,
+            output,
+        } => export::flat::export_flat(&READER.current(), &filter, format, &output)?,
+        Command::ExportMattermost {
+            filter,
+            team,
+            team_display_name,
+            files_dir,
+            output,
+        } => export::mattermost::export_mattermost(
+            &READER.current(),
+            &filter,
+            &team,
+            team_display_name.as_deref(),
+            files_dir.as_deref(),
+            &output,
+        )?,
+        Command::ExportMatrix {
+            filter,
+            server_name,
+            output,
+        } => export::matrix::export_matrix(&READER.current(), &filter, &server_name, &output)?,
+        Command::RetentionReport => {
+            let reader = READER.current();
+            for (channel, date, reason) in reader.expired_days()? {
+                println!("{}\t{}", reader.day_file(channel, date).display(), reason);
+            }
+        }
+        Command::Validate { json, strict } => {
+            let report = validate::validate(&config::config().data_path)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report.print_text();
+            }
+            if report.errors > 0 || (strict && report.warnings > 0) {
+                std::process::exit(1);
+            }
+        }
+        Command::Index { index } => {
+            let index = index
+                .or_else(|| config::config().index_path.clone())
+                .ok_or_else(|| anyhow::anyhow!("Index path is not set in config nor with --index"))?;
+            let stats = reader::update_index(&config::config().data_path, &index)?;
+            log::info!(
+                "Indexed {} channels, {} users, {} days with {} messages, {} days unchanged, {} removed",
+                stats.channels,
+                stats.users,
+                stats.days_indexed,
+                stats.messages_indexed,
+                stats.days_unchanged,
+                stats.days_removed
+            );
+        }
+        Command::SchemaReport { json } => {
+            let report = drift::drift_report(&READER.current(), &config::config().data_path)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report.print_text();
+            }
+        }
+    }
+    Ok(())
+}
+
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    setup_logger().unwrap();
+async fn serve() -> std::io::Result<()> {
+    reload::reload_on_hangup()?;
     HttpServer::new(|| {
         App::new()
-            .data(READER.clone())
+            .wrap(error::ErrorPages)
+            .wrap(audit::AuditTrail)
+            .wrap(auth::Authentication)
+            .wrap(metrics::RequestMetrics)
+            .wrap(Compress::default())
+            .configure(auth::configure)
+            .configure(audit::configure)
+            .configure(reload::configure)
+            .configure(metrics::configure)
             .service(list_dates)
             .service(ui::routes())
+            .default_service(web::route().to(|| async { Err::<String, _>(WebError::PageNotFound) }))
     })
-    .bind("127.0.0.1:8080")?
+    .bind(&config::config().bind)?
     .run()
     .await
 }
@@ -0,0 +1,341 @@
+//! Prometheus metrics at `/metrics`, liveness and readiness probes at `/healthz` and `/readyz`
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    web::ServiceConfig,
+    Error, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use serde::Serialize;
+
+use crate::{config::config, simple_cache::CacheStats, ui, READER};
+
+const NAMESPACE: &str = "slack_archive";
+
+lazy_static! {
+    static ref REGISTRY: Registry = {
+        let registry = Registry::new();
+        registry
+            .register(Box::new(ArchiveCollector::new()))
+            .unwrap();
+        registry
+    };
+    static ref HTTP_REQUESTS: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled requests").namespace(NAMESPACE),
+            &["route", "method", "status"],
+        )
+        .unwrap()
+    );
+    static ref HTTP_DURATION: HistogramVec = register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to respond to request"
+            )
+            .namespace(NAMESPACE),
+            &["route", "method"],
+        )
+        .unwrap()
+    );
+    pub static ref TEMPLATE_RENDER: HistogramVec = register(
+        HistogramVec::new(
+            HistogramOpts::new("template_render_duration_seconds", "Time to render page")
+                .namespace(NAMESPACE)
+                .buckets(vec![
+                    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0
+                ]),
+            &["template"],
+        )
+        .unwrap()
+    );
+    pub static ref DAY_LOAD: HistogramVec = register(
+        HistogramVec::new(
+            HistogramOpts::new("day_load_duration_seconds", "Time to read and parse day")
+                .namespace(NAMESPACE)
+                .buckets(vec![
+                    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0
+                ]),
+            &["backend"],
+        )
+        .unwrap()
+    );
+    pub static ref DAY_LOAD_BYTES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("day_load_bytes_total", "Bytes of day content parsed").namespace(NAMESPACE),
+            &["backend"],
+        )
+        .unwrap()
+    );
+}
+
+fn register<C: Collector + Clone + 'static>(collector: C) -> C {
+    REGISTRY.register(Box::new(collector.clone())).unwrap();
+    collector
+}
+
+/// Values read from caches and reader at scrape time
+struct ArchiveCollector {
+    cache_hits: IntCounterVec,
+    cache_misses: IntCounterVec,
+    cache_loads: IntCounterVec,
+    cache_coalesced: IntCounterVec,
+    cache_entries: IntGaugeVec,
+    cache_weight: IntGaugeVec,
+    channels: IntGauge,
+    users: IntGauge,
+    /// Concurrent scrapes would mix values while resetting
+    collecting: Mutex<()>,
+}
+
+impl ArchiveCollector {
+    fn new() -> Self {
+        let counter = |name: &str, help: &str| {
+            IntCounterVec::new(Opts::new(name, help).namespace(NAMESPACE), &["cache"]).unwrap()
+        };
+        let gauge = |name: &str, help: &str| {
+            IntGaugeVec::new(Opts::new(name, help).namespace(NAMESPACE), &["cache"]).unwrap()
+        };
+        Self {
+            cache_hits: counter("cache_hits_total", "Lookups answered from cache"),
+            cache_misses: counter("cache_misses_total", "Lookups not found in cache"),
+            cache_loads: counter("cache_loads_total", "Values computed on miss"),
+            cache_coalesced: counter(
+                "cache_coalesced_total",
+                "Misses served by value computed for concurrent lookup",
+            ),
+            cache_entries: gauge("cache_entries", "Entries in cache"),
+            cache_weight: gauge("cache_weight", "Weight of entries, bytes for day cache"),
+            channels: IntGauge::with_opts(
+                Opts::new("channels", "Loaded channels").namespace(NAMESPACE),
+            )
+            .unwrap(),
+            users: IntGauge::with_opts(Opts::new("users", "Loaded users").namespace(NAMESPACE))
+                .unwrap(),
+            collecting: Mutex::new(()),
+        }
+    }
+
+    fn record_cache(&self, name: &str, stats: CacheStats) {
+        self.cache_hits
+            .with_label_values(&[name])
+            .inc_by(stats.hits);
+        self.cache_misses
+            .with_label_values(&[name])
+            .inc_by(stats.misses);
+        self.cache_loads
+            .with_label_values(&[name])
+            .inc_by(stats.loads);
+        self.cache_coalesced
+            .with_label_values(&[name])
+            .inc_by(stats.coalesced);
+        self.cache_entries
+            .with_label_values(&[name])
+            .set(stats.entries as i64);
+        self.cache_weight
+            .with_label_values(&[name])
+            .set(stats.weight as i64);
+    }
+
+    fn counters(&self) -> [&IntCounterVec; 4] {
+        [
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.cache_loads,
+            &self.cache_coalesced,
+        ]
+    }
+}
+
+impl Collector for ArchiveCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        for counter in &self.counters() {
+            descs.extend(counter.desc());
+        }
+        descs.extend(self.cache_entries.desc());
+        descs.extend(self.cache_weight.desc());
+        descs.extend(self.channels.desc());
+        descs.extend(self.users.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _collecting = self.collecting.lock();
+        for counter in &self.counters() {
+            counter.reset();
+        }
+        self.cache_entries.reset();
+        self.cache_weight.reset();
+
+        let reader = READER.current();
+        for (name, stats) in ui::cache_stats() {
+            self.record_cache(name, stats);
+        }
+        if let Some(stats) = reader.day_cache_stats() {
+            self.record_cache("day", stats);
+        }
+        self.channels.set(reader.list_channels().len() as i64);
+        self.users.set(reader.list_users().len() as i64);
+
+        let mut families = Vec::new();
+        for counter in &self.counters() {
+            families.extend(counter.collect());
+        }
+        families.extend(self.cache_entries.collect());
+        families.extend(self.cache_weight.collect());
+        families.extend(self.channels.collect());
+        families.extend(self.users.collect());
+        families
+    }
+}
+
+/// Endpoints for monitoring, reachable without logging in and not audited
+pub fn is_monitoring(path: &str) -> bool {
+    path == "/metrics" || path == "/healthz" || path == "/readyz"
+}
+
+#[get("/metrics")]
+async fn metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(err) => {
+            log::error!("Failed to encode metrics: {:#}", err);
+            HttpResponse::InternalServerError().body("Failed to encode metrics")
+        }
+    }
+}
+
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    channels: usize,
+    users: usize,
+    error: Option<String>,
+}
+
+/// Ready when export is loaded, last reload succeeded and export is still in place
+#[get("/readyz")]
+async fn readyz() -> HttpResponse {
+    let reader = READER.current();
+    let config = config();
+    let missing = match &config.index_path {
+        Some(index_path) => vec![index_path.clone()],
+        None => vec![
+            config.data_path.join("channels.json"),
+            config.data_path.join("users.json"),
+        ],
+    }
+    .into_iter()
+    .find(|path| !path.exists());
+    let error = READER
+        .last_error()
+        .or_else(|| missing.map(|path| format!("{} does not exist", path.display())));
+
+    let readiness = Readiness {
+        ready: error.is_none(),
+        channels: reader.list_channels().len(),
+        users: reader.list_users().len(),
+        error,
+    };
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(metrics).service(healthz).service(readyz);
+}
+
+/// Middleware counting requests and their latency per route pattern
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S> for RequestMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestMetricsMiddleware { service })
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        // patterns, not paths, so channels and dates don't explode number of series
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let method = req.method().to_string();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let res = response.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            HTTP_REQUESTS
+                .with_label_values(&[&route, &method, status.as_str()])
+                .inc();
+            HTTP_DURATION
+                .with_label_values(&[&route, &method])
+                .observe(start.elapsed().as_secs_f64());
+            res
+        })
+    }
+}
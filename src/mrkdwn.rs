@@ -0,0 +1,370 @@
+//! Parser for Slack `mrkdwn` message text, so it can be converted to other markups
+
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    Text(String),
+    Bold(Vec<Node>),
+    Italic(Vec<Node>),
+    Strike(Vec<Node>),
+    Code(String),
+    Pre(String),
+    User(String),
+    Channel {
+        id: String,
+        name: Option<String>,
+    },
+    Link {
+        url: String,
+        label: Option<String>,
+    },
+    /// `@here`, `@channel` and `@everyone`
+    Broadcast(String),
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(Node::Text(last)) = nodes.last_mut() {
+        last.push_str(&unescape(text));
+    } else {
+        nodes.push(Node::Text(unescape(text)));
+    }
+}
+
+/// Parse content of `<...>` entity
+fn parse_entity(entity: &str) -> Node {
+    let (target, label) = match entity.find('|') {
+        Some(pos) => (&entity[..pos], Some(unescape(&entity[pos + 1..]))),
+        None => (entity, None),
+    };
+    if let Some(user) = target.strip_prefix('@') {
+        Node::User(user.to_string())
+    } else if let Some(channel) = target.strip_prefix('#') {
+        Node::Channel {
+            id: channel.to_string(),
+            name: label,
+        }
+    } else if let Some(special) = target.strip_prefix('!') {
+        match special {
+            "here" | "channel" | "everyone" => Node::Broadcast(special.to_string()),
+            _ => Node::Text(label.unwrap_or_else(|| format!("@{}", special))),
+        }
+    } else {
+        Node::Link {
+            url: unescape(target),
+            label,
+        }
+    }
+}
+
+fn is_boundary(c: Option<char>) -> bool {
+    c.map_or(true, |c| !c.is_alphanumeric())
+}
+
+/// Position of closing emphasis `marker` for one opened at start of `rest`
+fn find_closing(rest: &str, marker: char) -> Option<usize> {
+    let mut prev = None;
+    for (pos, c) in rest.char_indices().skip(1) {
+        if c == '\n' {
+            return None;
+        }
+        if c == marker
+            && pos > marker.len_utf8()
+            && prev.map_or(false, |p: char| !p.is_whitespace())
+            && is_boundary(rest[pos + c.len_utf8()..].chars().next())
+        {
+            return Some(pos);
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+pub fn parse(text: &str) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+    let mut prev_char = None;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+        let mut parsed = None;
+
+        if rest.starts_with("```") {
+            if let Some(end) = rest[3..].find("```") {
+                let code = rest[3..3 + end].trim_matches('\n');
+                parsed = Some((Node::Pre(unescape(code)), end + 6));
+            }
+        } else if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                parsed = Some((Node::Code(unescape(&rest[1..=end])), end + 2));
+            }
+        } else if c == '<' {
+            if let Some(end) = rest.find('>') {
+                parsed = Some((parse_entity(&rest[1..end]), end + 1));
+            }
+        } else if (c == '*' || c == '_' || c == '~') && is_boundary(prev_char) {
+            if let Some(end) = find_closing(rest, c) {
+                let inner = parse(&rest[1..end]);
+                let node = match c {
+                    '*' => Node::Bold(inner),
+                    '_' => Node::Italic(inner),
+                    _ => Node::Strike(inner),
+                };
+                parsed = Some((node, end + 1));
+            }
+        }
+
+        match parsed {
+            Some((node, len)) => {
+                push_text(&mut nodes, &text[text_start..pos]);
+                nodes.push(node);
+                pos += len;
+                text_start = pos;
+                prev_char = text[..pos].chars().last();
+            }
+            None => {
+                pos += c.len_utf8();
+                prev_char = Some(c);
+            }
+        }
+    }
+    push_text(&mut nodes, &text[text_start..]);
+    nodes
+}
+
+/// Names used when converting mentions
+pub trait Resolver {
+    fn user_name(&self, user_id: &str) -> String;
+    fn channel_name(&self, channel_id: &str) -> Option<String>;
+
+    /// Link to user profile, used by html output
+    fn user_url(&self, _user_id: &str) -> Option<String> {
+        None
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_markdown(out: &mut String, nodes: &[Node], resolver: &dyn Resolver) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Bold(inner) => {
+                out.push_str("**");
+                write_markdown(out, inner, resolver);
+                out.push_str("**");
+            }
+            Node::Italic(inner) => {
+                out.push('_');
+                write_markdown(out, inner, resolver);
+                out.push('_');
+            }
+            Node::Strike(inner) => {
+                out.push_str("~~");
+                write_markdown(out, inner, resolver);
+                out.push_str("~~");
+            }
+            Node::Code(code) => {
+                out.push('`');
+                out.push_str(code);
+                out.push('`');
+            }
+            Node::Pre(code) => {
+                out.push_str("```\n");
+                out.push_str(code);
+                out.push_str("\n```");
+            }
+            Node::User(id) => {
+                out.push('@');
+                out.push_str(&resolver.user_name(id));
+            }
+            Node::Channel { id, name } => {
+                out.push('~');
+                match resolver.channel_name(id).or_else(|| name.clone()) {
+                    Some(name) => out.push_str(&name),
+                    None => out.push_str(id),
+                }
+            }
+            Node::Link { url, label } => match label {
+                Some(label) => out.push_str(&format!("[{}]({})", label, url)),
+                None => out.push_str(url),
+            },
+            Node::Broadcast(target) => {
+                out.push('@');
+                out.push_str(if target == "everyone" { "all" } else { target });
+            }
+        }
+    }
+}
+
+/// Convert to common Markdown, mentions become `@user` and `~channel`
+pub fn to_markdown(text: &str, resolver: &dyn Resolver) -> String {
+    let mut out = String::with_capacity(text.len());
+    write_markdown(&mut out, &parse(text), resolver);
+    out
+}
+
+/// Only these links are kept in html, others like `javascript:` are shown as text
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+fn is_safe_url(url: &str) -> bool {
+    url.find(':').map_or(false, |end| {
+        SAFE_SCHEMES
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(&url[..end]))
+    })
+}
+
+fn write_html(out: &mut String, nodes: &[Node], resolver: &dyn Resolver) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&escape_html(text).replace('\n', "<br>")),
+            Node::Bold(inner) => {
+                out.push_str("<strong>");
+                write_html(out, inner, resolver);
+                out.push_str("</strong>");
+            }
+            Node::Italic(inner) => {
+                out.push_str("<em>");
+                write_html(out, inner, resolver);
+                out.push_str("</em>");
+            }
+            Node::Strike(inner) => {
+                out.push_str("<del>");
+                write_html(out, inner, resolver);
+                out.push_str("</del>");
+            }
+            Node::Code(code) => {
+                out.push_str("<code>");
+                out.push_str(&escape_html(code));
+                out.push_str("</code>");
+            }
+            Node::Pre(code) => {
+                out.push_str("<pre><code>");
+                out.push_str(&escape_html(code));
+                out.push_str("</code></pre>");
+            }
+            Node::User(id) => {
+                let name = escape_html(&resolver.user_name(id));
+                match resolver.user_url(id) {
+                    Some(url) => {
+                        out.push_str(&format!("<a href=\"{}\">{}</a>", escape_html(&url), name))
+                    }
+                    None => out.push_str(&format!("@{}", name)),
+                }
+            }
+            Node::Channel { id, name } => {
+                out.push('#');
+                out.push_str(&escape_html(
+                    &resolver
+                        .channel_name(id)
+                        .or_else(|| name.clone())
+                        .unwrap_or_else(|| id.clone()),
+                ));
+            }
+            Node::Link { url, label } => {
+                let label = escape_html(label.as_ref().unwrap_or(url));
+                if is_safe_url(url) {
+                    out.push_str(&format!("<a href=\"{}\">{}</a>", escape_html(url), label));
+                } else {
+                    out.push_str(&label);
+                }
+            }
+            Node::Broadcast(target) => {
+                out.push('@');
+                out.push_str(target);
+            }
+        }
+    }
+}
+
+/// Convert to html fragment, all text is escaped
+pub fn to_html(text: &str, resolver: &dyn Resolver) -> String {
+    let mut out = String::with_capacity(text.len());
+    write_html(&mut out, &parse(text), resolver);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestResolver;
+
+    impl Resolver for TestResolver {
+        fn user_name(&self, user_id: &str) -> String {
+            format!("name_{}", user_id)
+        }
+
+        fn channel_name(&self, _channel_id: &str) -> Option<String> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let nodes = parse("hi <@U1> *bold _it_* a*b* `*x*` &lt;3");
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Text("hi ".to_string()),
+                Node::User("U1".to_string()),
+                Node::Text(" ".to_string()),
+                Node::Bold(vec![
+                    Node::Text("bold ".to_string()),
+                    Node::Italic(vec![Node::Text("it".to_string())]),
+                ]),
+                Node::Text(" a*b* ".to_string()),
+                Node::Code("*x*".to_string()),
+                Node::Text(" <3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let markdown = to_markdown(
+            "<!here> ~gone~ see <https://x.org|site> in <#C1|general>\n```let a = 1;```",
+            &TestResolver,
+        );
+        assert_eq!(
+            markdown,
+            "@here ~~gone~~ see [site](https://x.org) in ~general\n```\nlet a = 1;\n```"
+        );
+        assert_eq!(to_markdown("cc <@U2>", &TestResolver), "cc @name_U2");
+    }
+
+    #[test]
+    fn test_to_html() {
+        assert_eq!(
+            to_html("*a* <b> &lt;i&gt; <@U1>\n<https://x.org>", &TestResolver),
+            "<strong>a</strong> b &lt;i&gt; @name_U1<br><a href=\"https://x.org\">https://x.org</a>"
+        );
+    }
+
+    #[test]
+    fn test_html_link_schemes() {
+        assert_eq!(
+            to_html("<mailto:a@x.org|mail> <HTTP://x.org|x>", &TestResolver),
+            "<a href=\"mailto:a@x.org\">mail</a> <a href=\"HTTP://x.org\">x</a>"
+        );
+        assert_eq!(
+            to_html("<javascript:alert(1)|run> <data:a,x>", &TestResolver),
+            "run data:a,x"
+        );
+    }
+}
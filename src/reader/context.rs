@@ -0,0 +1,197 @@
+//! Messages around a timestamp, which may come from several day files
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::{ChannelMessages, Entry, MessagesReader};
+
+/// Message of context together with the day it was loaded from
+pub struct ContextMessage {
+    pub date: NaiveDate,
+    day: Arc<ChannelMessages>,
+    index: usize,
+}
+
+impl ContextMessage {
+    pub fn entry(&self) -> &Entry {
+        &self.day.messages[self.index]
+    }
+}
+
+pub struct MessageContext {
+    /// Messages in chronological order
+    pub messages: Vec<ContextMessage>,
+    /// Position of first message posted at or after requested time, equal to length of
+    /// `messages` when there is none
+    pub anchor: usize,
+}
+
+/// Indices of day messages ordered by time, exports are usually sorted already
+fn ordered(day: &ChannelMessages) -> Vec<usize> {
+    let mut indices: Vec<_> = (0..day.messages.len()).collect();
+    indices.sort_by_key(|index| day.messages[*index].timestamp);
+    indices
+}
+
+/// Walk days from the one of `ts` in both directions until enough messages are found
+fn collect_context(
+    dates: &[NaiveDate],
+    ts: DateTime<Utc>,
+    before: usize,
+    after: usize,
+    mut load: impl FnMut(NaiveDate) -> Result<Arc<ChannelMessages>>,
+) -> Result<MessageContext> {
+    // files are split by days in timezone of workspace, so neighbouring days are
+    // searched too
+    let day = ts.naive_utc().date();
+    let first = dates
+        .iter()
+        .position(|date| *date >= day.pred())
+        .unwrap_or(dates.len());
+    let last = dates
+        .iter()
+        .position(|date| *date > day.succ())
+        .unwrap_or(dates.len());
+    let mut loaded: HashMap<NaiveDate, Arc<ChannelMessages>> = HashMap::new();
+    let mut load_day = |date: NaiveDate| -> Result<Arc<ChannelMessages>> {
+        if let Some(day) = loaded.get(&date) {
+            return Ok(day.clone());
+        }
+        let day = load(date)?;
+        loaded.insert(date, day.clone());
+        Ok(day)
+    };
+
+    let mut preceding = Vec::new();
+    for date in dates[..last].iter().rev() {
+        if preceding.len() >= before {
+            break;
+        }
+        let messages = load_day(*date)?;
+        for index in ordered(&messages).into_iter().rev() {
+            if preceding.len() >= before {
+                break;
+            }
+            if messages.messages[index].timestamp < ts {
+                preceding.push(ContextMessage {
+                    date: *date,
+                    day: messages.clone(),
+                    index,
+                });
+            }
+        }
+    }
+    preceding.reverse();
+
+    // anchor itself is followed by `after` messages
+    let mut following = Vec::new();
+    for date in &dates[first..] {
+        if following.len() > after {
+            break;
+        }
+        let messages = load_day(*date)?;
+        for index in ordered(&messages) {
+            if following.len() > after {
+                break;
+            }
+            if messages.messages[index].timestamp >= ts {
+                following.push(ContextMessage {
+                    date: *date,
+                    day: messages.clone(),
+                    index,
+                });
+            }
+        }
+    }
+
+    let anchor = preceding.len();
+    preceding.extend(following);
+    Ok(MessageContext {
+        messages: preceding,
+        anchor,
+    })
+}
+
+impl MessagesReader {
+    /// Up to `before` messages posted before `ts`, followed by the first message at or
+    /// after `ts` and up to `after` messages past it
+    pub fn message_context(
+        &self,
+        channel_id: &str,
+        ts: DateTime<Utc>,
+        before: usize,
+        after: usize,
+    ) -> Result<MessageContext> {
+        let mut dates = self.list_dates(channel_id)?;
+        dates.sort();
+        collect_context(&dates, ts, before, after, |date| {
+            self.channel_messages_parse(channel_id, &date)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reader::floating_timestamp;
+    use chrono::TimeZone;
+
+    fn day(timestamps: &[i64]) -> Arc<ChannelMessages> {
+        let messages: Vec<_> = timestamps
+            .iter()
+            .map(|ts| {
+                serde_json::json!({
+                    "type": "message",
+                    "text": "",
+                    "user": "U1",
+                    "ts": format!("{}.000100", ts),
+                })
+            })
+            .collect();
+        Arc::new(serde_json::from_value(serde_json::Value::Array(messages)).unwrap())
+    }
+
+    #[test]
+    fn test_context_crosses_days() {
+        let first = NaiveDate::from_ymd(2020, 10, 10);
+        let second = NaiveDate::from_ymd(2020, 10, 12);
+        let midnight = Utc.ymd(2020, 10, 12).and_hms(0, 0, 0).timestamp();
+        let days: HashMap<_, _> = vec![
+            (first, day(&[midnight - 20, midnight - 10])),
+            (second, day(&[midnight + 20, midnight + 10, midnight + 30])),
+        ]
+        .into_iter()
+        .collect();
+        let load = |date| Ok(days[&date].clone());
+
+        let context = collect_context(
+            &[first, second],
+            floating_timestamp::parse(&format!("{}.000100", midnight + 10)).unwrap(),
+            2,
+            1,
+            load,
+        )
+        .unwrap();
+        let times: Vec<_> = context
+            .messages
+            .iter()
+            .map(|message| message.entry().timestamp.timestamp() - midnight)
+            .collect();
+        assert_eq!(times, vec![-20, -10, 10, 20]);
+        assert_eq!(context.anchor, 2);
+        assert_eq!(context.messages[2].date, second);
+
+        let context = collect_context(
+            &[first, second],
+            Utc.timestamp(midnight + 60, 0),
+            1,
+            5,
+            load,
+        )
+        .unwrap();
+        assert_eq!(context.messages.len(), 1);
+        assert_eq!(context.anchor, 1);
+    }
+}
@@ -0,0 +1,466 @@
+//! SQLite index of whole export, so requests don't need to parse day files
+
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use parking_lot::{Mutex, MutexGuard};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Transaction};
+
+use crate::metrics;
+
+use super::{
+    floating_timestamp::to_slack_ts,
+    store::{DayStore, DayVersion, FsStore},
+    ChannelInfo, ChannelMessages, MessagesReader, User,
+};
+
+/// Stored as `user_version`, index of other version is rebuilt by `update_index`
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS channels (
+    id TEXT PRIMARY KEY,
+    info TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    info TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS days (
+    channel_id TEXT NOT NULL,
+    date TEXT NOT NULL,
+    mtime INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    message_count INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, date)
+);
+CREATE TABLE IF NOT EXISTS messages (
+    channel_id TEXT NOT NULL,
+    date TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    ts TEXT NOT NULL,
+    -- CBOR encoded parsed `Entry`, much faster to load than JSON
+    entry BLOB NOT NULL,
+    PRIMARY KEY (channel_id, date, position)
+);
+";
+
+const DROP_SCHEMA: &str = "
+DROP TABLE IF EXISTS channels;
+DROP TABLE IF EXISTS users;
+DROP TABLE IF EXISTS days;
+DROP TABLE IF EXISTS messages;
+";
+
+/// Summary of `update_index` run
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    pub channels: usize,
+    pub users: usize,
+    pub days_indexed: usize,
+    pub days_unchanged: usize,
+    pub days_removed: usize,
+    pub messages_indexed: usize,
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    Connection::open(path).with_context(|| format!("Failed to open index {}", path.display()))
+}
+
+fn schema_version(conn: &Connection) -> Result<i64> {
+    Ok(conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?)
+}
+
+/// Create schema, index of older version is emptied so everything is indexed again
+fn prepare_schema(conn: &Connection) -> Result<()> {
+    if schema_version(conn)? != SCHEMA_VERSION {
+        conn.execute_batch(DROP_SCHEMA)
+            .context("Failed to drop outdated index")?;
+    }
+    conn.execute_batch(SCHEMA)
+        .context("Failed to create index schema")?;
+    conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+    Ok(())
+}
+
+/// Replace messages of day `key`, which is `(channel id, date)`
+fn index_day(
+    tx: &Transaction,
+    key: &(String, String),
+    version: (i64, i64),
+    messages: &ChannelMessages,
+) -> Result<()> {
+    tx.execute(
+        "DELETE FROM messages WHERE channel_id = ?1 AND date = ?2",
+        params![key.0, key.1],
+    )?;
+    for (position, entry) in messages.messages.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO messages (channel_id, date, position, ts, entry)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                key.0,
+                key.1,
+                position as i64,
+                to_slack_ts(&entry.timestamp),
+                serde_cbor::to_vec(entry)?
+            ],
+        )?;
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO days (channel_id, date, mtime, size, message_count)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            key.0,
+            key.1,
+            version.0,
+            version.1,
+            messages.messages.len() as i64
+        ],
+    )?;
+    Ok(())
+}
+
+/// Bring index at `index_path` up to date with export, only changed day files are parsed
+pub fn update_index(data_path: &Path, index_path: &Path) -> Result<IndexStats> {
+    let data_path = data_path.to_path_buf();
+    let channels = MessagesReader::parse_channels(&data_path)?;
+    let users = MessagesReader::parse_users(&data_path)?;
+    let files = FsStore {
+        data_path: data_path.clone(),
+    };
+
+    let mut conn = open(index_path)?;
+    prepare_schema(&conn)?;
+    let tx = conn.transaction()?;
+    let mut stats = IndexStats {
+        channels: channels.len(),
+        users: users.len(),
+        ..IndexStats::default()
+    };
+
+    tx.execute("DELETE FROM channels", params![])?;
+    for channel in channels.values() {
+        tx.execute(
+            "INSERT INTO channels (id, info) VALUES (?1, ?2)",
+            params![channel.id, serde_json::to_string(channel)?],
+        )?;
+    }
+    tx.execute("DELETE FROM users", params![])?;
+    for user in users.values() {
+        tx.execute(
+            "INSERT INTO users (id, info) VALUES (?1, ?2)",
+            params![user.id, serde_json::to_string(user)?],
+        )?;
+    }
+
+    let mut indexed: HashMap<(String, String), (i64, i64)> = HashMap::new();
+    {
+        let mut select = tx.prepare("SELECT channel_id, date, mtime, size FROM days")?;
+        let rows = select.query_map(params![], |row| {
+            Ok(((row.get(0)?, row.get(1)?), (row.get(2)?, row.get(3)?)))
+        })?;
+        for row in rows {
+            let (key, version) = row?;
+            indexed.insert(key, version);
+        }
+    }
+
+    for channel in channels.values() {
+        let dates = match files.dates(channel) {
+            Ok(dates) => dates,
+            Err(err) => {
+                log::warn!("Skipping channel {}: {:#}", channel.name, err);
+                continue;
+            }
+        };
+        for date in dates {
+            let path = files.day_path(channel, date);
+            let version = DayVersion::of_file(&path)?;
+            let version = (version.mtime, version.size as i64);
+            let key = (channel.id.clone(), date.to_string());
+            if indexed.remove(&key) == Some(version) {
+                stats.days_unchanged += 1;
+                continue;
+            }
+
+            let messages: ChannelMessages = match read_to_string(&path)
+                .context("Failed to read day")
+                .and_then(|content| Ok(serde_json::from_str(&content)?))
+            {
+                Ok(messages) => messages,
+                Err(err) => {
+                    log::warn!("Skipping {}: {:#}", path.display(), err);
+                    continue;
+                }
+            };
+            index_day(&tx, &key, version, &messages)?;
+            stats.days_indexed += 1;
+            stats.messages_indexed += messages.messages.len();
+        }
+    }
+
+    // whatever was not seen on disk is gone from export
+    for (channel_id, date) in indexed.keys() {
+        tx.execute(
+            "DELETE FROM messages WHERE channel_id = ?1 AND date = ?2",
+            params![channel_id, date],
+        )?;
+        tx.execute(
+            "DELETE FROM days WHERE channel_id = ?1 AND date = ?2",
+            params![channel_id, date],
+        )?;
+        stats.days_removed += 1;
+    }
+
+    tx.commit()?;
+    Ok(stats)
+}
+
+/// Read-only connections kept by `SqliteStore`, so days can be loaded in parallel
+const READ_CONNECTIONS: usize = 4;
+
+/// Reader backend answering from index built by `update_index`
+pub struct SqliteStore {
+    conns: Vec<Mutex<Connection>>,
+    next: AtomicUsize,
+}
+
+impl SqliteStore {
+    pub fn open(index_path: &Path) -> Result<Self> {
+        if !index_path.exists() {
+            return Err(anyhow!(
+                "Index {} does not exist, create it with `index` command",
+                index_path.display()
+            ));
+        }
+        let conns = (0..READ_CONNECTIONS)
+            .map(|_| {
+                let conn = Connection::open_with_flags(
+                    index_path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )
+                .with_context(|| format!("Failed to open index {}", index_path.display()))?;
+                Ok(Mutex::new(conn))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if schema_version(&conns[0].lock())? != SCHEMA_VERSION {
+            return Err(anyhow!(
+                "Index {} was created by another version, update it with `index` command",
+                index_path.display()
+            ));
+        }
+        Ok(Self {
+            conns,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Free connection when there is one, otherwise wait for the next one in turn
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conns
+            .iter()
+            .find_map(|conn| conn.try_lock())
+            .unwrap_or_else(|| {
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                self.conns[next % self.conns.len()].lock()
+            })
+    }
+
+    pub fn channels(&self) -> Result<HashMap<String, ChannelInfo>> {
+        let conn = self.conn();
+        let mut select = conn.prepare("SELECT info FROM channels")?;
+        let rows = select.query_map(params![], |row| row.get::<_, String>(0))?;
+        let mut channels = HashMap::new();
+        for info in rows {
+            let channel: ChannelInfo = serde_json::from_str(&info?)?;
+            channels.insert(channel.id.clone(), channel);
+        }
+        Ok(channels)
+    }
+
+    pub fn users(&self) -> Result<HashMap<String, User>> {
+        let conn = self.conn();
+        let mut select = conn.prepare("SELECT info FROM users")?;
+        let rows = select.query_map(params![], |row| row.get::<_, String>(0))?;
+        let mut users = HashMap::new();
+        for info in rows {
+            let user: User = serde_json::from_str(&info?)?;
+            users.insert(user.id.clone(), user);
+        }
+        Ok(users)
+    }
+}
+
+impl DayStore for SqliteStore {
+    fn dates(&self, channel: &ChannelInfo) -> Result<Vec<NaiveDate>> {
+        let conn = self.conn();
+        let mut select = conn.prepare_cached("SELECT date FROM days WHERE channel_id = ?1")?;
+        let rows = select.query_map(params![channel.id], |row| row.get::<_, String>(0))?;
+        let mut dates = Vec::new();
+        for date in rows {
+            dates.push(date?.parse()?);
+        }
+        Ok(dates)
+    }
+
+    fn version(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<DayVersion> {
+        self.conn()
+            .query_row(
+                "SELECT mtime, size FROM days WHERE channel_id = ?1 AND date = ?2",
+                params![channel.id, date.to_string()],
+                |row| {
+                    Ok(DayVersion {
+                        mtime: row.get(0)?,
+                        size: row.get::<_, i64>(1)? as u64,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("Failed to read day"))
+    }
+
+    fn load(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<ChannelMessages> {
+        // checked separately, day without messages is still a day
+        self.version(channel, date)?;
+        let _timer = metrics::DAY_LOAD
+            .with_label_values(&["index"])
+            .start_timer();
+        let entries = {
+            let conn = self.conn();
+            let mut select = conn.prepare_cached(
+                "SELECT entry FROM messages WHERE channel_id = ?1 AND date = ?2 ORDER BY position",
+            )?;
+            let rows = select.query_map(params![channel.id, date.to_string()], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        // decoded after connection is released for other requests
+        let mut messages = Vec::with_capacity(entries.len());
+        let mut bytes = 0;
+        for entry in entries {
+            bytes += entry.len() as u64;
+            messages.push(serde_cbor::from_slice(&entry)?);
+        }
+        metrics::DAY_LOAD_BYTES
+            .with_label_values(&["index"])
+            .inc_by(bytes);
+        Ok(ChannelMessages { messages })
+    }
+
+    fn message_count(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<usize> {
+        self.conn()
+            .query_row(
+                "SELECT message_count FROM days WHERE channel_id = ?1 AND date = ?2",
+                params![channel.id, date.to_string()],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .map(|count| count as usize)
+            .ok_or_else(|| anyhow!("Failed to read day"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::write, thread::sleep, time::Duration};
+
+    use super::*;
+    use crate::test_support::{create_export, message, write_json};
+
+    fn channel(store: &SqliteStore, id: &str) -> ChannelInfo {
+        store.channels().unwrap().remove(id).unwrap()
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_path = dir.path().join("export");
+        let index_path = dir.path().join("index.sqlite");
+        create_export(&data_path);
+        let day = NaiveDate::from_ymd(2020, 10, 11);
+        let next_day = day.succ();
+
+        let stats = update_index(&data_path, &index_path).unwrap();
+        assert_eq!((stats.channels, stats.users), (2, 2));
+        assert_eq!((stats.days_indexed, stats.messages_indexed), (3, 5));
+
+        let stats = update_index(&data_path, &index_path).unwrap();
+        assert_eq!((stats.days_indexed, stats.days_unchanged), (0, 3));
+
+        // loaded entries are the same as in day file
+        let store = SqliteStore::open(&index_path).unwrap();
+        let general = channel(&store, "C1");
+        let files = FsStore {
+            data_path: data_path.clone(),
+        };
+        assert_eq!(
+            serde_json::to_value(store.load(&general, day).unwrap()).unwrap(),
+            serde_json::to_value(files.load(&general, day).unwrap()).unwrap()
+        );
+        assert_eq!(store.message_count(&general, day).unwrap(), 3);
+
+        // different size
+        write_json(
+            &data_path.join("general/2020-10-12.json"),
+            &serde_json::json!([
+                message("1602489600.000100", "U1", "Too late", None),
+                message("1602489700.000100", "U2", "Indeed", None),
+            ]),
+        );
+        // same size, only modification time differs
+        sleep(Duration::from_millis(10));
+        let random_day = data_path.join("random/2020-10-11.json");
+        write(&random_day, std::fs::read(&random_day).unwrap()).unwrap();
+
+        let stats = update_index(&data_path, &index_path).unwrap();
+        assert_eq!((stats.days_indexed, stats.days_unchanged), (2, 1));
+        assert_eq!(stats.messages_indexed, 3);
+        assert_eq!(store.message_count(&general, next_day).unwrap(), 2);
+        assert_eq!(
+            store.load(&general, next_day).unwrap().messages[1].text,
+            "Indeed"
+        );
+
+        std::fs::remove_file(data_path.join("general/2020-10-11.json")).unwrap();
+        let stats = update_index(&data_path, &index_path).unwrap();
+        assert_eq!((stats.days_removed, stats.days_unchanged), (1, 2));
+        assert_eq!(store.dates(&general).unwrap(), vec![next_day]);
+        assert!(store.load(&general, day).is_err());
+        let count: i64 = store
+            .conn()
+            .query_row(
+                "SELECT COUNT(*) FROM messages WHERE channel_id = 'C1'",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_outdated_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_path = dir.path().join("export");
+        let index_path = dir.path().join("index.sqlite");
+        create_export(&data_path);
+        update_index(&data_path, &index_path).unwrap();
+        open(&index_path)
+            .unwrap()
+            .execute_batch("PRAGMA user_version = 1")
+            .unwrap();
+        assert!(SqliteStore::open(&index_path).is_err());
+
+        // everything is indexed again
+        let stats = update_index(&data_path, &index_path).unwrap();
+        assert_eq!((stats.days_indexed, stats.days_unchanged), (3, 0));
+        assert!(SqliteStore::open(&index_path).is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use super::floating_timestamp;
+use super::{floating_timestamp, optional_floating_timestamp};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
@@ -20,6 +20,59 @@ pub struct Entry {
     #[serde(rename = "ts")]
     #[serde(with = "floating_timestamp")]
     pub timestamp: DateTime<Utc>,
+    #[serde(default, with = "optional_floating_timestamp")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_ts: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<MessageFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited: Option<Edited>,
+}
+
+impl Entry {
+    /// Message posted inside a thread, but not the thread parent itself
+    pub fn is_thread_reply(&self) -> bool {
+        matches!(self.thread_ts, Some(thread_ts) if thread_ts != self.timestamp)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Edited {
+    #[serde(rename = "user")]
+    pub user_id: String,
+    #[serde(rename = "ts")]
+    #[serde(with = "floating_timestamp")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reaction {
+    pub name: String,
+    #[serde(default)]
+    pub users: Vec<String>,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MessageFile {
+    pub id: String,
+    pub name: Option<String>,
+    pub title: Option<String>,
+    pub mimetype: Option<String>,
+    pub url_private: Option<String>,
+}
+
+impl MessageFile {
+    pub fn display_name(&self) -> &str {
+        self.title
+            .as_deref()
+            .or_else(|| self.name.as_deref())
+            .unwrap_or(&self.id)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,18 +1,43 @@
+mod context;
+mod index;
 mod messages;
+mod redact;
+mod retention;
+mod store;
 mod timestamp;
 mod user;
 
-use std::{collections::HashMap, fs::read_to_string, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use anyhow::{anyhow, Context, Result};
 use chrono::prelude::*;
+use crate::{
+    config::{RedactionConfig, RetentionConfig},
+    simple_cache::{CacheStats, ConcurrentCache},
+};
 use lru::LruCache;
+use once_cell::sync::OnceCell;
+pub use context::MessageContext;
+pub use index::update_index;
 pub use messages::*;
+pub use redact::{RedactMode, Redactor, PREVIEW_END, PREVIEW_START};
+pub use retention::{Retention, Verdict};
+pub use store::DayVersion;
+use store::{DayStore, FsStore};
 use serde::{Deserialize, Serialize};
-pub use timestamp::floating_timestamp;
+pub use timestamp::{floating_timestamp, optional_floating_timestamp};
 pub use user::*;
 
-#[derive(Eq, PartialEq, Hash)]
+/// Days with message counts kept by reader, counts are needed for every page of range
+const COUNTED_DAYS: usize = 65536;
+
+#[derive(Eq, PartialEq, Hash, Clone)]
 struct MessagesChannelId {
     channel_id: String,
     date: NaiveDate,
@@ -21,53 +46,286 @@ struct MessagesChannelId {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChannelInfo {
     pub id: String,
+    /// Direct messages have no name, their id is used instead
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+    pub topic: Option<ChannelText>,
+    pub purpose: Option<ChannelText>,
+    /// Set from file channel was listed in
+    #[serde(default)]
+    pub kind: ChannelKind,
+}
+
+/// Kind of conversation, by file of export it comes from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    /// `channels.json`
+    Public,
+    /// `groups.json`
+    Private,
+    /// `mpims.json`
+    Group,
+    /// `dms.json`
+    Direct,
+}
+
+impl Default for ChannelKind {
+    fn default() -> Self {
+        ChannelKind::Public
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelText {
+    pub value: String,
+    #[serde(default)]
+    pub creator: String,
+    #[serde(default)]
+    pub last_set: i64,
+}
+
+/// Overview of channel history, as far as retention allows to see it
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ChannelSummary {
+    pub messages: usize,
+    pub last_activity: Option<NaiveDate>,
 }
 
 pub struct MessagesReader {
-    data_path: PathBuf,
+    store: Box<dyn DayStore>,
+    /// Parsed days, keyed with version so modified files are parsed again
+    day_cache: Option<ConcurrentCache<(MessagesChannelId, DayVersion), ChannelMessages>>,
+    /// Message counts of days, keyed with version like parsed days
+    count_cache: ConcurrentCache<(MessagesChannelId, DayVersion), usize>,
     channels: HashMap<String, ChannelInfo>,
     users: HashMap<String, User>,
+    /// User ids by lowercase email, kept aside as emails may be redacted
+    user_emails: HashMap<String, String>,
+    redactor: Option<Redactor>,
+    retention: Option<Retention>,
+    loaded_at: SystemTime,
+    /// Computed by background job, requires going through every day
+    summaries: OnceCell<HashMap<String, ChannelSummary>>,
 }
 
 impl MessagesReader {
-    pub fn new(data_path: PathBuf) -> Self {
+    pub fn new(data_path: PathBuf) -> Result<Self> {
+        Ok(Self::with_store(
+            Self::parse_channels(&data_path)?,
+            Self::parse_users(&data_path)?,
+            Box::new(FsStore { data_path }),
+        ))
+    }
+
+    /// Reader answering from index built by `index` command instead of day files
+    pub fn from_index(index_path: &Path) -> Result<Self> {
+        let index = index::SqliteStore::open(index_path)?;
+        Ok(Self::with_store(
+            index.channels()?,
+            index.users()?,
+            Box::new(index),
+        ))
+    }
+
+    fn with_store(
+        channels: HashMap<String, ChannelInfo>,
+        users: HashMap<String, User>,
+        store: Box<dyn DayStore>,
+    ) -> Self {
         Self {
-            channels: Self::parse_channels(&data_path).unwrap(),
-            users: Self::parse_users(&data_path).unwrap(),
-            data_path,
+            channels,
+            store,
+            day_cache: None,
+            count_cache: ConcurrentCache::new(COUNTED_DAYS),
+            user_emails: users
+                .values()
+                .filter_map(|user| {
+                    let email = user.profile.email.as_ref()?;
+                    Some((email.to_lowercase(), user.id.clone()))
+                })
+                .collect(),
+            users,
+            redactor: None,
+            retention: None,
+            loaded_at: SystemTime::now(),
+            summaries: OnceCell::new(),
         }
     }
 
+    /// Mask personal data in everything returned by reader
+    pub fn with_redaction(mut self, config: &RedactionConfig) -> Result<Self> {
+        let redactor = Redactor::new(config, &self.users)?;
+        for user in self.users.values_mut() {
+            redactor.redact_user(user);
+        }
+        for channel in self.channels.values_mut() {
+            redactor.redact_channel(channel);
+        }
+        self.redactor = Some(redactor);
+        Ok(self)
+    }
+
+    /// Hide days outside of retention policies
+    pub fn with_retention(mut self, config: &RetentionConfig) -> Self {
+        self.retention = Some(Retention::new(config.clone()));
+        self
+    }
+
+    /// Keep parsed days in memory, up to `budget` bytes of their source files
+    pub fn with_day_cache(mut self, budget: usize) -> Self {
+        self.day_cache = Some(ConcurrentCache::with_budget(
+            budget,
+            |(_, version): &(MessagesChannelId, DayVersion), _| version.size as usize,
+        ))
+        .filter(|_| budget > 0);
+        self
+    }
+
+    /// When channels and users were loaded, pages listing them can't be older
+    pub fn loaded_at(&self) -> SystemTime {
+        self.loaded_at
+    }
+
+    pub fn day_cache_stats(&self) -> Option<CacheStats> {
+        self.day_cache.as_ref().map(ConcurrentCache::stats)
+    }
+
+    fn is_retained(&self, channel: &ChannelInfo, date: NaiveDate) -> bool {
+        self.retention
+            .as_ref()
+            .map_or(true, |retention| retention.is_visible(channel, date))
+    }
+
     pub fn channel_messages_parse(
         &self,
         channel_id: &str,
         date: &NaiveDate,
+    ) -> Result<Arc<ChannelMessages>> {
+        let channel_info = self.get_channel(channel_id)?;
+        match &self.day_cache {
+            Some(cache) => {
+                if !self.is_retained(channel_info, *date) {
+                    return Err(anyhow!("Day is outside of retention policy"));
+                }
+                let key = MessagesChannelId {
+                    channel_id: channel_id.to_string(),
+                    date: *date,
+                };
+                let version = self.store.version(channel_info, *date)?;
+                cache.get_or_try_insert_with((key, version), |_| {
+                    self.channel_messages_load(channel_id, date, RedactMode::Apply)
+                })
+            }
+            None => Ok(Arc::new(self.channel_messages_load(
+                channel_id,
+                date,
+                RedactMode::Apply,
+            )?)),
+        }
+    }
+
+    /// Version of stored day, fails like `channel_messages_parse` for days that can't be shown
+    pub fn day_version(&self, channel_id: &str, date: &NaiveDate) -> Result<DayVersion> {
+        let channel_info = self.get_channel(channel_id)?;
+        if !self.is_retained(channel_info, *date) {
+            return Err(anyhow!("Day is outside of retention policy"));
+        }
+        self.store.version(channel_info, *date)
+    }
+
+    /// Number of messages on day, counted once per version of day and without parsing
+    /// messages fully when store allows it
+    pub fn day_message_count(&self, channel_id: &str, date: &NaiveDate) -> Result<usize> {
+        let channel_info = self.get_channel(channel_id)?;
+        if !self.is_retained(channel_info, *date) {
+            return Err(anyhow!("Day is outside of retention policy"));
+        }
+        let key = MessagesChannelId {
+            channel_id: channel_id.to_string(),
+            date: *date,
+        };
+        let version = self.store.version(channel_info, *date)?;
+        let count = self
+            .count_cache
+            .get_or_try_insert_with((key, version), |_| {
+                self.store.message_count(channel_info, *date)
+            })?;
+        Ok(*count)
+    }
+
+    /// Same as `channel_messages_parse`, but bypassing day cache, for jobs going through
+    /// every day once
+    pub fn channel_messages_uncached(
+        &self,
+        channel_id: &str,
+        date: &NaiveDate,
+    ) -> Result<ChannelMessages> {
+        self.channel_messages_load(channel_id, date, RedactMode::Apply)
+    }
+
+    /// Messages with text that would be redacted marked instead of replaced
+    pub fn channel_messages_preview(
+        &self,
+        channel_id: &str,
+        date: &NaiveDate,
+    ) -> Result<ChannelMessages> {
+        self.channel_messages_load(channel_id, date, RedactMode::Preview)
+    }
+
+    fn channel_messages_load(
+        &self,
+        channel_id: &str,
+        date: &NaiveDate,
+        mode: RedactMode,
     ) -> Result<ChannelMessages> {
         let channel_info = self
             .channels
             .get(channel_id)
             .ok_or(anyhow!("Channel not found"))?;
+        if !self.is_retained(channel_info, *date) {
+            return Err(anyhow!("Day is outside of retention policy"));
+        }
 
-        let data_path = self
-            .data_path
-            .join(&channel_info.name)
-            .join(format!("{}.json", date));
-        let messages = serde_json::from_str(
-            &read_to_string(data_path).context(anyhow!("Failed to read day"))?,
-        )?;
+        let mut messages = self.store.load(channel_info, *date)?;
+        if let Some(redactor) = self.redactor.as_ref().filter(|r| !r.is_exempt(channel_info)) {
+            for entry in &mut messages.messages {
+                redactor.redact_entry(entry, mode);
+            }
+        }
 
        Ok(messages)
     }
 
     fn parse_channels(data_path: &PathBuf) -> Result<HashMap<String, ChannelInfo>> {
-        let channels: Vec<ChannelInfo> = serde_json::from_str(
+        let mut channels: Vec<ChannelInfo> = serde_json::from_str(
             &read_to_string(data_path.join("channels.json")).context("Failed to load channels")?,
         )
         .context("Failed to parse channels list")?;
 
+        // private channels and direct messages are present only in some exports
+        for &(optional, kind) in &[
+            ("groups.json", ChannelKind::Private),
+            ("mpims.json", ChannelKind::Group),
+            ("dms.json", ChannelKind::Direct),
+        ] {
+            let path = data_path.join(optional);
+            if path.exists() {
+                let conversations: Vec<ChannelInfo> = serde_json::from_str(
+                    &read_to_string(&path).with_context(|| format!("Failed to load {}", optional))?,
+                )
+                .with_context(|| format!("Failed to parse {}", optional))?;
+                channels.extend(conversations.into_iter().map(|channel| ChannelInfo { kind, ..channel }));
+            }
+        }
+
         let mut channels_map = HashMap::new();
-        for channel in channels {
+        for mut channel in channels {
+            if channel.name.is_empty() {
+                channel.name = channel.id.clone();
+            }
             channels_map.insert(channel.id.to_owned(), channel);
         }
 
@@ -88,6 +346,12 @@ impl MessagesReader {
         Ok(users_map)
     }
 
+    pub fn get_channel(&self, channel_id: &str) -> Result<&ChannelInfo> {
+        self.channels
+            .get(channel_id)
+            .ok_or(anyhow!("Channel not found"))
+    }
+
     pub fn get_channel_name(&self, channel_id: &str) -> Result<&str> {
         self.channels
             .get(channel_id)
@@ -95,41 +359,150 @@ impl MessagesReader {
             .ok_or(anyhow!("Channel not found"))
     }
 
+    /// Find channel by its id or name
+    pub fn resolve_channel(&self, id_or_name: &str) -> Result<&ChannelInfo> {
+        self.channels
+            .get(id_or_name)
+            .or_else(|| self.channels.values().find(|ch| ch.name == id_or_name))
+            .ok_or(anyhow!("Channel not found"))
+    }
+
     pub fn get_user_info(&self, user_id: &str) -> Result<&User> {
         self.users.get(user_id).ok_or(anyhow!("User not found"))
     }
 
+    /// Find user by id or user name
+    pub fn resolve_user(&self, id_or_name: &str) -> Result<&User> {
+        self.users
+            .get(id_or_name)
+            .or_else(|| self.users.values().find(|user| user.name == id_or_name))
+            .ok_or(anyhow!("User not found"))
+    }
+
     pub fn list_channels(&self) -> Vec<&ChannelInfo> {
         self.channels.values().collect()
     }
 
+    pub fn find_user_by_email(&self, email: &str) -> Option<&User> {
+        self.user_emails
+            .get(&email.to_lowercase())
+            .and_then(|user_id| self.users.get(user_id))
+    }
+
+    pub fn list_users(&self) -> Vec<&User> {
+        self.users.values().collect()
+    }
+
     pub fn list_dates(&self, channel_id: &str) -> Result<Vec<NaiveDate>> {
-        let channel_name = self.get_channel_name(channel_id)?;
-        let mut dates = Vec::new();
-        for dir in std::fs::read_dir(self.data_path.join(channel_name))
-            .with_context(|| format!("Failed to read channel: {}", channel_name))?
-        {
-            let entry =
-                dir.with_context(|| format!("Failed to read channel dir: {}", channel_name))?;
-            let file_path = entry.path();
-            if file_path.is_file() {
-                let file_name = file_path.file_name().unwrap();
-                let date = NaiveDate::parse_from_str(&file_name.to_string_lossy(), "%Y-%m-%d.json");
-                match date {
-                    Ok(date) => {
-                        dates.push(date);
-                    }
-                    Err(err) => {
-                        log::warn!(
-                            "Invalid date in channel {} history: {:#}",
-                            channel_name,
-                            err
-                        );
-                    }
+        let channel = self.get_channel(channel_id)?;
+        let mut dates = self.list_stored_dates(channel_id)?;
+        dates.retain(|date| self.is_retained(channel, *date));
+        Ok(dates)
+    }
+
+    /// Days outside of retention policy and not under legal hold, with reason
+    pub fn expired_days(&self) -> Result<Vec<(&ChannelInfo, NaiveDate, String)>> {
+        let retention = match &self.retention {
+            Some(retention) => retention,
+            None => return Ok(Vec::new()),
+        };
+        let today = Utc::today().naive_utc();
+        let mut expired = Vec::new();
+        for channel in self.channels.values() {
+            let dates = match self.list_stored_dates(&channel.id) {
+                Ok(dates) => dates,
+                Err(err) => {
+                    log::warn!("Skipping channel {}: {:#}", channel.name, err);
+                    continue;
+                }
+            };
+            for date in dates {
+                if let Verdict::Expired(reason) = retention.verdict(channel, date, today) {
+                    expired.push((channel, date, reason));
                 }
             }
         }
+        expired.sort_by(|a, b| (&a.0.name, a.1).cmp(&(&b.0.name, b.1)));
+        Ok(expired)
+    }
 
-        Ok(dates)
+    /// Path of day file relative to data directory
+    pub fn day_file(&self, channel: &ChannelInfo, date: NaiveDate) -> PathBuf {
+        PathBuf::from(&channel.name).join(format!("{}.json", date))
+    }
+
+    /// Summary of channel, `None` until `compute_summaries` is done
+    pub fn channel_summary(&self, channel_id: &str) -> Option<ChannelSummary> {
+        self.summaries
+            .get()
+            .map(|summaries| summaries.get(channel_id).cloned().unwrap_or_default())
+    }
+
+    pub fn summaries_ready(&self) -> bool {
+        self.summaries.get().is_some()
+    }
+
+    /// Go through every day of all channels, slow for large exports
+    pub fn compute_summaries(&self) {
+        self.summaries.get_or_init(|| {
+            self.channels
+                .values()
+                .map(|channel| (channel.id.clone(), self.summarize(channel)))
+                .collect()
+        });
+    }
+
+    fn summarize(&self, channel: &ChannelInfo) -> ChannelSummary {
+        let dates = match self.list_dates(&channel.id) {
+            Ok(dates) => dates,
+            Err(err) => {
+                log::warn!("Failed to summarize channel {}: {:#}", channel.name, err);
+                return ChannelSummary::default();
+            }
+        };
+        let mut summary = ChannelSummary {
+            last_activity: dates.iter().max().copied(),
+            ..ChannelSummary::default()
+        };
+        for date in dates {
+            match self.store.message_count(channel, date) {
+                Ok(count) => summary.messages += count,
+                Err(err) => log::warn!("Failed to count {} on {}: {:#}", channel.name, date, err),
+            }
+        }
+        summary
+    }
+
+    /// All days present in export, regardless of retention
+    pub fn list_stored_dates(&self, channel_id: &str) -> Result<Vec<NaiveDate>> {
+        self.store.dates(self.get_channel(channel_id)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{self, message, PARENT_TS};
+
+    #[test]
+    fn test_day_message_count() {
+        let dir = tempfile::tempdir().unwrap();
+        test_support::create_export(dir.path());
+        let reader = MessagesReader::new(dir.path().to_path_buf()).unwrap();
+        let date = NaiveDate::from_ymd(2020, 10, 11);
+
+        assert_eq!(reader.day_message_count("C1", &date).unwrap(), 3);
+        assert_eq!(reader.day_message_count("C1", &date).unwrap(), 3);
+        let stats = reader.count_cache.stats();
+        assert_eq!((stats.loads, stats.hits), (1, 1));
+
+        // modified day is counted again
+        test_support::write_json(
+            &dir.path().join("general/2020-10-11.json"),
+            &json!([message(PARENT_TS, "U1", "Lunch?", None)]),
+        );
+        assert_eq!(reader.day_message_count("C1", &date).unwrap(), 1);
     }
 }
@@ -0,0 +1,247 @@
+//! Masking of personal data before messages leave the reader
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+
+use super::{ChannelInfo, Entry, User};
+use crate::config::RedactionConfig;
+
+/// Marks around matched text in preview mode, private use characters survive html escaping
+pub const PREVIEW_START: char = '\u{E000}';
+pub const PREVIEW_END: char = '\u{E001}';
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RedactMode {
+    /// Replace matches
+    Apply,
+    /// Keep original text with matches wrapped in `PREVIEW_START` and `PREVIEW_END`
+    Preview,
+}
+
+struct Rule {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl Rule {
+    fn apply(&self, text: &str, mode: RedactMode) -> String {
+        match mode {
+            RedactMode::Apply => self
+                .pattern
+                .replace_all(text, self.replacement.as_str())
+                .into_owned(),
+            RedactMode::Preview => self
+                .pattern
+                .replace_all(text, |caps: &Captures| {
+                    format!("{}{}{}", PREVIEW_START, &caps[0], PREVIEW_END)
+                })
+                .into_owned(),
+        }
+    }
+}
+
+pub struct Redactor {
+    rules: Vec<Rule>,
+    /// Pseudonyms by user id
+    pseudonyms: HashMap<String, String>,
+    /// Channel ids and names not redacted
+    exempt_channels: HashSet<String>,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig, users: &HashMap<String, User>) -> Result<Self> {
+        let mut rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    pattern: Regex::new(&rule.pattern).with_context(|| {
+                        format!("Invalid pattern of redaction rule {}", rule.name)
+                    })?,
+                    replacement: rule.replacement.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let everyone = config.pseudonymize_users.iter().any(|id| id == "*");
+        let mut pseudonyms = HashMap::new();
+        for user in users.values() {
+            if !everyone && !config.pseudonymize_users.contains(&user.id) {
+                continue;
+            }
+            let mut hasher = Sha256::new();
+            hasher.update(config.pseudonym_salt.as_bytes());
+            hasher.update(user.id.as_bytes());
+            let pseudonym = format!("user-{}", &format!("{:x}", hasher.finalize())[..8]);
+
+            // names written in text are masked as well
+            let mut names: Vec<_> = [
+                &user.name,
+                &user.profile.real_name,
+                &user.profile.display_name,
+            ]
+            .iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| regex::escape(name))
+            .collect();
+            names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+            names.dedup();
+            // empty alternation would match at every word boundary
+            if !names.is_empty() {
+                rules.push(Rule {
+                    pattern: Regex::new(&format!(r"(?i)\b(?:{})\b", names.join("|")))?,
+                    replacement: pseudonym.clone(),
+                });
+            }
+            pseudonyms.insert(user.id.clone(), pseudonym);
+        }
+
+        Ok(Self {
+            rules,
+            pseudonyms,
+            exempt_channels: config.exempt_channels.iter().cloned().collect(),
+        })
+    }
+
+    pub fn is_exempt(&self, channel: &ChannelInfo) -> bool {
+        self.exempt_channels.contains(&channel.id) || self.exempt_channels.contains(&channel.name)
+    }
+
+    pub fn redact_text(&self, text: &str, mode: RedactMode) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, rule| rule.apply(&text, mode))
+    }
+
+    pub fn redact_entry(&self, entry: &mut Entry, mode: RedactMode) {
+        entry.text = self.redact_text(&entry.text, mode);
+        for file in &mut entry.files {
+            file.name = file.name.as_ref().map(|name| self.redact_text(name, mode));
+            file.title = file
+                .title
+                .as_ref()
+                .map(|title| self.redact_text(title, mode));
+        }
+        if let (Some(details), Some(pseudonym)) =
+            (&mut entry.details, self.pseudonyms.get(&entry.user_id))
+        {
+            let profile = &mut details.user_profile;
+            profile.name = pseudonym.clone();
+            profile.real_name = pseudonym.clone();
+            profile.display_name = pseudonym.clone();
+            profile.first_name = pseudonym.clone();
+        }
+    }
+
+    /// Replace names and email of pseudonymised user. Email of other users is dropped
+    /// when rules match it, exports use placeholder address instead.
+    pub fn redact_user(&self, user: &mut User) {
+        if let Some(pseudonym) = self.pseudonyms.get(&user.id) {
+            user.name = pseudonym.clone();
+            user.profile.real_name = pseudonym.clone();
+            user.profile.display_name = pseudonym.clone();
+            user.profile.email = None;
+        } else if let Some(email) = &user.profile.email {
+            if self.redact_text(email, RedactMode::Apply) != *email {
+                user.profile.email = None;
+            }
+        }
+    }
+
+    pub fn redact_channel(&self, channel: &mut ChannelInfo) {
+        if self.is_exempt(channel) {
+            return;
+        }
+        for text in channel.topic.iter_mut().chain(channel.purpose.iter_mut()) {
+            text.value = self.redact_text(&text.value, RedactMode::Apply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RedactionRule;
+
+    #[test]
+    fn test_redact_text() {
+        let user: User = serde_json::from_str(
+            r#"{"id": "U1", "name": "jdoe", "profile": {"display_name": "", "real_name": "John Doe"}}"#,
+        )
+        .unwrap();
+        let config = RedactionConfig {
+            rules: vec![RedactionRule {
+                name: "email".to_string(),
+                pattern: r"[\w.+-]+@[\w-]+\.[\w.]+".to_string(),
+                replacement: "[email]".to_string(),
+            }],
+            pseudonymize_users: vec!["U1".to_string()],
+            ..RedactionConfig::default()
+        };
+        let users = vec![("U1".to_string(), user)].into_iter().collect();
+        let redactor = Redactor::new(&config, &users).unwrap();
+        let pseudonym = &redactor.pseudonyms["U1"];
+
+        assert_eq!(
+            redactor.redact_text("mail john doe at jd@example.com", RedactMode::Apply),
+            format!("mail {} at [email]", pseudonym)
+        );
+        assert_eq!(
+            redactor.redact_text("jd@example.com", RedactMode::Preview),
+            format!("{}jd@example.com{}", PREVIEW_START, PREVIEW_END)
+        );
+    }
+
+    #[test]
+    fn test_pseudonymize_without_names() {
+        let user: User = serde_json::from_str(
+            r#"{"id": "U1", "name": "", "profile": {"display_name": "", "real_name": ""}}"#,
+        )
+        .unwrap();
+        let config = RedactionConfig {
+            pseudonymize_users: vec!["U1".to_string()],
+            ..RedactionConfig::default()
+        };
+        let users = vec![("U1".to_string(), user)].into_iter().collect();
+        let redactor = Redactor::new(&config, &users).unwrap();
+
+        assert!(redactor.pseudonyms.contains_key("U1"));
+        assert_eq!(
+            redactor.redact_text("hello world", RedactMode::Apply),
+            "hello world"
+        );
+    }
+
+    #[test]
+    fn test_redact_user_email() {
+        let user = || -> User {
+            serde_json::from_str(
+                r#"{"id": "U2", "name": "amy", "profile": {"display_name": "", "real_name": "Amy", "email": "amy@example.com"}}"#,
+            )
+            .unwrap()
+        };
+        let users = vec![("U2".to_string(), user())].into_iter().collect();
+
+        let redactor = Redactor::new(&RedactionConfig::default(), &users).unwrap();
+        let mut kept = user();
+        redactor.redact_user(&mut kept);
+        assert_eq!(kept.profile.email.as_deref(), Some("amy@example.com"));
+
+        let config = RedactionConfig {
+            rules: vec![RedactionRule {
+                name: "email".to_string(),
+                pattern: r"[\w.+-]+@[\w-]+\.[\w.]+".to_string(),
+                replacement: "[email]".to_string(),
+            }],
+            ..RedactionConfig::default()
+        };
+        let redactor = Redactor::new(&config, &users).unwrap();
+        let mut redacted = user();
+        redactor.redact_user(&mut redacted);
+        assert_eq!(redacted.name, "amy");
+        assert_eq!(redacted.profile.email, None);
+    }
+}
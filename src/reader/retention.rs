@@ -0,0 +1,132 @@
+//! Which days of history may be presented according to retention policies and legal holds
+
+use chrono::{Duration, NaiveDate, Utc};
+
+use super::ChannelInfo;
+use crate::config::{DateRange, RetentionConfig, RetentionPolicy};
+
+/// Decision about single day of channel history
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict<'a> {
+    Keep,
+    /// Outside policy, but kept by legal hold with this name
+    Held(&'a str),
+    /// Outside policy, with reason
+    Expired(String),
+}
+
+impl Verdict<'_> {
+    pub fn is_visible(&self) -> bool {
+        !matches!(self, Verdict::Expired(_))
+    }
+}
+
+fn matches_channel(patterns: &[String], channel: &ChannelInfo) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| pattern == "*" || *pattern == channel.id || *pattern == channel.name)
+}
+
+fn in_range(range: &DateRange, date: NaiveDate) -> bool {
+    range.from.map_or(true, |from| date >= from) && range.to.map_or(true, |to| date <= to)
+}
+
+pub struct Retention {
+    config: RetentionConfig,
+}
+
+impl Retention {
+    pub fn new(config: RetentionConfig) -> Self {
+        Self { config }
+    }
+
+    fn policy(&self, channel: &ChannelInfo) -> Option<&RetentionPolicy> {
+        self.config
+            .channels
+            .iter()
+            .find(|policy| matches_channel(&policy.channels, channel))
+            .map(|policy| &policy.policy)
+            .or_else(|| self.config.default.as_ref())
+    }
+
+    pub fn verdict(&self, channel: &ChannelInfo, date: NaiveDate, today: NaiveDate) -> Verdict<'_> {
+        let expired = match self.policy(channel) {
+            Some(policy) => {
+                if let Some(keep_days) = policy
+                    .keep_days
+                    .filter(|days| date < today - Duration::days(i64::from(*days)))
+                {
+                    Some(format!("older than {} days", keep_days))
+                } else if !policy.ranges.is_empty()
+                    && !policy.ranges.iter().any(|range| in_range(range, date))
+                {
+                    Some("outside kept date ranges".to_string())
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        match expired {
+            None => Verdict::Keep,
+            Some(reason) => self
+                .config
+                .holds
+                .iter()
+                .find(|hold| {
+                    matches_channel(&hold.channels, channel) && in_range(&hold.range, date)
+                })
+                .map_or(Verdict::Expired(reason), |hold| Verdict::Held(&hold.name)),
+        }
+    }
+
+    pub fn is_visible(&self, channel: &ChannelInfo, date: NaiveDate) -> bool {
+        self.verdict(channel, date, Utc::today().naive_utc())
+            .is_visible()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ChannelPolicy, LegalHold};
+
+    #[test]
+    fn test_verdict() {
+        let date = |s: &str| s.parse::<NaiveDate>().unwrap();
+        let channel: ChannelInfo =
+            serde_json::from_str(r#"{"id": "C1", "name": "general"}"#).unwrap();
+        let retention = Retention::new(RetentionConfig {
+            default: None,
+            channels: vec![ChannelPolicy {
+                channels: vec!["general".to_string()],
+                policy: RetentionPolicy {
+                    keep_days: Some(30),
+                    ranges: Vec::new(),
+                },
+            }],
+            holds: vec![LegalHold {
+                name: "case-1".to_string(),
+                channels: vec!["C1".to_string()],
+                range: DateRange {
+                    from: Some(date("2020-01-01")),
+                    to: Some(date("2020-01-31")),
+                },
+            }],
+        });
+        let today = date("2020-06-01");
+
+        assert_eq!(
+            retention.verdict(&channel, date("2020-05-15"), today),
+            Verdict::Keep
+        );
+        assert_eq!(
+            retention.verdict(&channel, date("2020-01-15"), today),
+            Verdict::Held("case-1")
+        );
+        assert!(!retention
+            .verdict(&channel, date("2020-02-15"), today)
+            .is_visible());
+    }
+}
@@ -0,0 +1,110 @@
+//! Backends answering which days a channel has and what was said on them
+
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use serde::de::IgnoredAny;
+
+use super::{ChannelInfo, ChannelMessages};
+use crate::metrics;
+
+/// Identifies content of stored day, changes whenever day file is modified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DayVersion {
+    /// Modification time in nanoseconds since epoch
+    pub mtime: i64,
+    /// Size of source file in bytes
+    pub size: u64,
+}
+
+impl DayVersion {
+    pub fn of_file(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            mtime: metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |mtime| mtime.as_nanos() as i64),
+            size: metadata.len(),
+        })
+    }
+}
+
+pub trait DayStore: Send + Sync {
+    /// Days with history of channel, in no particular order
+    fn dates(&self, channel: &ChannelInfo) -> Result<Vec<NaiveDate>>;
+    fn version(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<DayVersion>;
+    fn load(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<ChannelMessages>;
+    /// Number of messages on day, without parsing them fully when possible
+    fn message_count(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<usize>;
+}
+
+/// Day files of unpacked export, `<channel name>/<date>.json`
+pub struct FsStore {
+    pub data_path: PathBuf,
+}
+
+impl FsStore {
+    pub fn day_path(&self, channel: &ChannelInfo, date: NaiveDate) -> PathBuf {
+        self.data_path
+            .join(&channel.name)
+            .join(format!("{}.json", date))
+    }
+}
+
+impl DayStore for FsStore {
+    fn dates(&self, channel: &ChannelInfo) -> Result<Vec<NaiveDate>> {
+        let channel_name = &channel.name;
+        let mut dates = Vec::new();
+        for dir in std::fs::read_dir(self.data_path.join(channel_name))
+            .with_context(|| format!("Failed to read channel: {}", channel_name))?
+        {
+            let entry =
+                dir.with_context(|| format!("Failed to read channel dir: {}", channel_name))?;
+            let file_path = entry.path();
+            if file_path.is_file() {
+                let file_name = file_path.file_name().unwrap();
+                let date = NaiveDate::parse_from_str(&file_name.to_string_lossy(), "%Y-%m-%d.json");
+                match date {
+                    Ok(date) => {
+                        dates.push(date);
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            "Invalid date in channel {} history: {:#}",
+                            channel_name,
+                            err
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(dates)
+    }
+
+    fn version(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<DayVersion> {
+        DayVersion::of_file(&self.day_path(channel, date)).context(anyhow!("Failed to read day"))
+    }
+
+    fn load(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<ChannelMessages> {
+        let _timer = metrics::DAY_LOAD.with_label_values(&["files"]).start_timer();
+        let data_path = self.day_path(channel, date);
+        let content = read_to_string(data_path).context(anyhow!("Failed to read day"))?;
+        metrics::DAY_LOAD_BYTES
+            .with_label_values(&["files"])
+            .inc_by(content.len() as u64);
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn message_count(&self, channel: &ChannelInfo, date: NaiveDate) -> Result<usize> {
+        let content = read_to_string(self.day_path(channel, date))
+            .context(anyhow!("Failed to read day"))?;
+        Ok(serde_json::from_str::<Vec<IgnoredAny>>(&content)?.len())
+    }
+}
@@ -1,4 +1,6 @@
 pub mod floating_timestamp {
+    use std::num::ParseFloatError;
+
     use chrono::{DateTime, TimeZone, Utc};
     use serde::{de, Deserialize, Deserializer, Serializer};
 
@@ -13,6 +15,18 @@ pub mod floating_timestamp {
         serializer.serialize_str(&s)
     }
 
+    /// Format timestamp the way Slack does in `ts` fields, it is also message identifier
+    pub fn to_slack_ts(date: &DateTime<Utc>) -> String {
+        let mut seconds = date.timestamp();
+        // parsing goes through f64, so round to microseconds Slack is using
+        let mut micros = (date.timestamp_subsec_nanos() + 500) / 1000;
+        if micros >= 1_000_000 {
+            seconds += 1;
+            micros -= 1_000_000;
+        }
+        format!("{}.{:06}", seconds, micros)
+    }
+
     // The signature of a deserialize_with function must follow the pattern:
     //
     //    fn deserialize<'de, D>(D) -> Result<T, D::Error>
@@ -25,9 +39,13 @@ pub mod floating_timestamp {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let floating: f64 = s
-            .parse()
-            .map_err(|err| de::Error::custom(format_args!("Failed to parse timestamp: {}", err)))?;
+        parse(&s)
+            .map_err(|err| de::Error::custom(format_args!("Failed to parse timestamp: {}", err)))
+    }
+
+    /// Parse `ts` the same way as when it is read from export, so they can be compared
+    pub fn parse(s: &str) -> Result<DateTime<Utc>, ParseFloatError> {
+        let floating: f64 = s.parse()?;
         let seconds = floating as i64;
         let ns = (floating - seconds as f64) * 1_000_000_000f64;
         Ok(Utc.timestamp(seconds, ns as u32))
@@ -62,5 +80,39 @@ pub mod floating_timestamp {
             assert_eq!(date.date.timestamp(), 12345);
             assert_eq!(date.date.timestamp_subsec_nanos(), 123);
         }
+
+        #[test]
+        fn test_slack_ts_roundtrip() {
+            let serialized = "\"1602404763.211500\"";
+            let date: TestDate = serde_json::from_str(serialized).unwrap();
+            assert_eq!(super::to_slack_ts(&date.date), "1602404763.211500");
+        }
+    }
+}
+
+/// Same as `floating_timestamp` for optional fields, e.g. `thread_ts`
+pub mod optional_floating_timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match date {
+            Some(date) => super::floating_timestamp::serialize(date, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(transparent)]
+        struct Wrapper(#[serde(with = "super::floating_timestamp")] DateTime<Utc>);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|wrapper| wrapper.0))
     }
 }
@@ -12,4 +12,17 @@ pub struct User {
 pub struct Profile {
     pub display_name: String,
     pub real_name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+impl User {
+    /// Name as presented by Slack: display name when set, account name otherwise
+    pub fn display_name(&self) -> &str {
+        if self.profile.display_name.is_empty() {
+            &self.name
+        } else {
+            &self.profile.display_name
+        }
+    }
 }
@@ -0,0 +1,242 @@
+//! Replacing reader with freshly loaded one when export changes, without restarting server.
+//! Reload is triggered by filesystem watcher, SIGHUP or `POST /admin/reload`.
+
+use std::{ops::Deref, sync::mpsc::channel, sync::Arc, thread, time::Duration};
+
+use actix_web::{
+    dev::Payload,
+    post,
+    web::{self, ServiceConfig},
+    FromRequest, HttpRequest, HttpResponse,
+};
+use anyhow::Result;
+use futures::future::{ok, Ready};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+
+use crate::{
+    authz::{Access, Viewer},
+    config::config,
+    error::{self, WebError},
+    load_reader,
+    reader::MessagesReader,
+    stats, ui, READER,
+};
+
+/// Reader which can be swapped while requests are being served, each request keeps
+/// using the reader it started with
+pub struct SharedReader {
+    current: RwLock<Arc<MessagesReader>>,
+    /// Only one reload at a time, loading can take a while for large exports
+    reloading: Mutex<()>,
+    /// Why last reload failed, cleared by successful one
+    last_error: RwLock<Option<String>>,
+}
+
+impl SharedReader {
+    pub fn new(reader: MessagesReader) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(reader)),
+            reloading: Mutex::new(()),
+            last_error: RwLock::new(None),
+        }
+    }
+
+    pub fn current(&self) -> Arc<MessagesReader> {
+        self.current.read().clone()
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.read().clone()
+    }
+
+    /// Load reader again from configured export and swap it in, on failure the old
+    /// one stays in use
+    pub fn reload(&self) -> Result<Arc<MessagesReader>> {
+        self.reload_with(load_reader)
+    }
+
+    fn reload_with(
+        &self,
+        load: impl FnOnce() -> Result<MessagesReader>,
+    ) -> Result<Arc<MessagesReader>> {
+        let _reloading = self.reloading.lock();
+        let reader = match load() {
+            Ok(reader) => Arc::new(reader),
+            Err(err) => {
+                *self.last_error.write() = Some(format!("{:#}", err));
+                return Err(err);
+            }
+        };
+        *self.current.write() = reader.clone();
+        *self.last_error.write() = None;
+        // cached fragments are keyed by load time, so requests still holding the old
+        // reader can't serve theirs to new ones, this only frees memory
+        ui::clear_caches();
+        stats::start(reader.clone());
+        log::info!(
+            "Reloaded export with {} channels and {} users",
+            reader.list_channels().len(),
+            reader.list_users().len()
+        );
+        Ok(reader)
+    }
+}
+
+/// Reader current at the time request arrived
+pub struct CurrentReader(Arc<MessagesReader>);
+
+impl CurrentReader {
+    /// For work outliving request handler, like streamed responses
+    pub fn shared(&self) -> Arc<MessagesReader> {
+        self.0.clone()
+    }
+}
+
+impl Deref for CurrentReader {
+    type Target = MessagesReader;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for CurrentReader {
+    type Config = ();
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(_req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ok(Self(READER.current()))
+    }
+}
+
+fn reload_logged() {
+    if let Err(err) = READER.reload() {
+        log::error!("Failed to reload export, keeping previous one: {:#}", err);
+    }
+}
+
+/// Reload when data directory, or index when used, changes. Changes are debounced,
+/// so export being copied in is loaded once it settles.
+pub fn watch() -> Result<()> {
+    let config = config();
+    if !config.reload.watch {
+        return Ok(());
+    }
+    let (path, mode) = match &config.index_path {
+        Some(index_path) => (index_path.clone(), RecursiveMode::NonRecursive),
+        None => (config.data_path.clone(), RecursiveMode::Recursive),
+    };
+
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::from_secs(config.reload.delay_secs))?;
+    watcher.watch(&path, mode)?;
+    log::info!("Watching {} for changes", path.display());
+
+    thread::spawn(move || {
+        // dropping watcher stops watching
+        let _watcher = watcher;
+        for event in rx {
+            match event {
+                DebouncedEvent::Create(_)
+                | DebouncedEvent::Write(_)
+                | DebouncedEvent::Remove(_)
+                | DebouncedEvent::Rename(_, _)
+                | DebouncedEvent::Rescan => reload_logged(),
+                DebouncedEvent::Error(err, path) => {
+                    log::warn!("Watching {:?} failed: {}", path, err);
+                }
+                DebouncedEvent::NoticeWrite(_)
+                | DebouncedEvent::NoticeRemove(_)
+                | DebouncedEvent::Chmod(_) => {}
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reload on every SIGHUP, has to be called from within actix system
+#[cfg(unix)]
+pub fn reload_on_hangup() -> std::io::Result<()> {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            log::info!("SIGHUP received, reloading export");
+            let _ = web::block(|| -> Result<(), ()> {
+                reload_logged();
+                Ok(())
+            })
+            .await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_hangup() -> std::io::Result<()> {
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ReloadResult {
+    channels: usize,
+    users: usize,
+}
+
+#[post("/admin/reload")]
+async fn reload_page(viewer: Viewer) -> error::Result<HttpResponse> {
+    if !matches!(viewer.access, Access::All) {
+        return Err(WebError::Forbidden);
+    }
+    let reader = web::block(|| READER.reload()).await?;
+    Ok(HttpResponse::Ok().json(ReloadResult {
+        channels: reader.list_channels().len(),
+        users: reader.list_users().len(),
+    }))
+}
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(reload_page);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        test_support,
+        ui::{sidebar::SortOrder, Links},
+    };
+
+    #[test]
+    fn test_reload() {
+        test_support::init_config();
+        let shared = SharedReader::new(test_support::reader());
+        let first = shared.current();
+        let key = (
+            first.loaded_at(),
+            Links::server(),
+            None,
+            vec!["test_reload".to_string()],
+            SortOrder::Name,
+        );
+        ui::sidebar::CHANNELS_LIST_CACHE.insert(key.clone(), String::new());
+
+        let failed = shared.reload_with(|| Err(anyhow::anyhow!("export is broken")));
+        assert!(failed.is_err());
+        assert!(Arc::ptr_eq(&shared.current(), &first));
+        assert_eq!(shared.last_error().as_deref(), Some("export is broken"));
+        assert!(ui::sidebar::CHANNELS_LIST_CACHE.get(&key).is_some());
+
+        let reloaded = shared.reload_with(|| Ok(test_support::reader())).unwrap();
+        assert!(Arc::ptr_eq(&shared.current(), &reloaded));
+        assert!(!Arc::ptr_eq(&reloaded, &first));
+        assert_eq!(shared.last_error(), None);
+        assert!(ui::sidebar::CHANNELS_LIST_CACHE.get(&key).is_none());
+        // requests which started before reload keep their reader
+        assert_eq!(first.list_channels().len(), reloaded.list_channels().len());
+    }
+}
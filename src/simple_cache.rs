@@ -1,145 +1,380 @@
-use std::{sync::Arc, marker::PhantomData, time::{Duration, Instant}};
+//! Concurrent cache shared between request handlers
 
-use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-pub trait Function<T>: Sync + Send
-where
-    T: Clone + Sync + Send,
-{
-    fn call(&self) -> T;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+/// Number of independently locked parts of cache
+const SHARDS: usize = 16;
+
+/// Counters of cache usage
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Values computed by cache users
+    pub loads: u64,
+    /// Misses served by value computed for other concurrent request
+    pub coalesced: u64,
+    pub entries: usize,
+    /// Sum of entry weights, number of entries when cache is not weighted
+    pub weight: usize,
 }
 
-pub struct TimeCache<T, F>
-where
-    F: Function<T>,
-    T: Clone + Sync + Send,
-{
-    last_refresh: Instant,
-    ttl: Duration,
-    content: Option<T>,
-    generate: F,
+struct Entry<V> {
+    value: Arc<V>,
+    weight: usize,
+    created: Instant,
+}
+
+struct Shard<K: Hash + Eq, V> {
+    /// Unbounded, entries are evicted by weight of whole cache
+    entries: Mutex<LruCache<K, Entry<V>>>,
+    /// Keys being computed, concurrent requests for them wait on the lock
+    loading: Mutex<HashMap<K, Arc<Mutex<()>>>>,
 }
 
-impl<T, F> TimeCache<T, F>
+/// LRU cache split into shards, lookups lock only one shard and values are computed
+/// outside of any lock, once for all concurrent requests of the same key
+pub struct ConcurrentCache<K: Hash + Eq, V> {
+    shards: Vec<Shard<K, V>>,
+    hasher: RandomState,
+    /// Limit of summed weights of all shards
+    budget: usize,
+    weight: AtomicUsize,
+    /// Shard to evict from next when the one being inserted into has nothing left
+    next_victim: AtomicUsize,
+    weigher: fn(&K, &V) -> usize,
+    ttl: Option<Duration>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    loads: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl<K, V> ConcurrentCache<K, V>
 where
-    F: Function<T>,
-    T: Clone + Sync + Send,
+    K: Hash + Eq + Clone,
 {
-    pub fn new(ttl: Duration, generate: F) -> Self {
+    /// Cache of up to `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self::with_budget(capacity, |_, _| 1)
+    }
+
+    /// Cache bounded by sum of entry weights, e.g. sizes in bytes
+    pub fn with_budget(budget: usize, weigher: fn(&K, &V) -> usize) -> Self {
         Self {
-            last_refresh: Instant::now(),
-            ttl,
-            content: None,
-            generate,
+            shards: (0..SHARDS)
+                .map(|_| Shard {
+                    entries: Mutex::new(LruCache::unbounded()),
+                    loading: Mutex::new(HashMap::new()),
+                })
+                .collect(),
+            hasher: RandomState::new(),
+            budget,
+            weight: AtomicUsize::new(0),
+            next_victim: AtomicUsize::new(0),
+            weigher,
+            ttl: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            loads: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
         }
     }
 
-    fn refresh(&mut self) {
-        self.last_refresh = Instant::now();
-        self.content = Some(self.generate.call());
+    /// Entries older than `ttl` are computed again
+    #[allow(dead_code)]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
     }
 
-    pub fn content(&mut self) -> &T {
-        if !self.is_valid() {
-            self.refresh();
-        }
+    fn shard(&self, key: &K) -> &Shard<K, V> {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
 
-        self.content.as_ref().unwrap()
+    fn is_expired(&self, entry: &Entry<V>) -> bool {
+        self.ttl.map_or(false, |ttl| entry.created.elapsed() >= ttl)
     }
 
-    pub fn is_valid(&self) -> bool {
-        self.content.is_some() && self.last_refresh.elapsed() < self.ttl
+    fn lookup(&self, shard: &Shard<K, V>, key: &K) -> Option<Arc<V>> {
+        let mut entries = shard.entries.lock();
+        match entries.get(key) {
+            Some(entry) if !self.is_expired(entry) => return Some(entry.value.clone()),
+            Some(_) => {}
+            None => return None,
+        }
+        if let Some(expired) = entries.pop(key) {
+            self.weight.fetch_sub(expired.weight, Ordering::Relaxed);
+        }
+        None
     }
 
-    pub fn peek(&self) -> Option<&T> {
-        self.content.as_ref()
+    #[allow(dead_code)]
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        let value = self.lookup(self.shard(key), key);
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
     }
-}
 
-impl<T, F> OptimisticLock<T> for TimeCache<T, F>
-where
-    F: Function<T>,
-    T: Clone + Sync + Send,
-{
-    fn try_read(&self) -> Option<T> {
-        if self.is_valid() {
-            self.peek().cloned()
-        } else {
-            None
+    pub fn get_or_insert_with<F>(&self, key: K, compute: F) -> Arc<V>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        match self
+            .get_or_try_insert_with::<_, std::convert::Infallible>(key, |key| Ok(compute(key)))
+        {
+            Ok(value) => value,
+            Err(never) => match never {},
         }
     }
 
-    fn read_mut(&mut self) -> T {
-        self.content().clone()
+    /// Cached value, or result of `compute` stored when it succeeds. Concurrent callers
+    /// with the same key wait for the first one instead of computing it again.
+    pub fn get_or_try_insert_with<F, E>(&self, key: K, compute: F) -> Result<Arc<V>, E>
+    where
+        F: FnOnce(&K) -> Result<V, E>,
+    {
+        let shard = self.shard(&key);
+        if let Some(value) = self.lookup(shard, &key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let flight = shard.loading.lock().entry(key.clone()).or_default().clone();
+        let _flight = flight.lock();
+        if let Some(value) = self.lookup(shard, &key) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        let result = compute(&key).map(|value| {
+            let value = Arc::new(value);
+            self.store(shard, key.clone(), value.clone());
+            value
+        });
+        shard.loading.lock().remove(&key);
+        result
     }
-}
 
-pub trait OptimisticLock<T> {
-    fn try_read(&self) -> Option<T>;
-    fn read_mut(&mut self) -> T;
-}
+    #[allow(dead_code)]
+    pub fn insert(&self, key: K, value: V) -> Arc<V> {
+        let value = Arc::new(value);
+        self.store(self.shard(&key), key, value.clone());
+        value
+    }
 
-pub struct OptimisticLockCache<T, C>
-where
-    T: OptimisticLock<C>,
-{
-    cache: RwLock<T>,
-    _phantom: PhantomData<C>,
-}
+    fn is_over_budget(&self) -> bool {
+        self.weight.load(Ordering::Relaxed) > self.budget
+    }
 
-impl<T, C> OptimisticLockCache<T, C>
-where
-    T: OptimisticLock<C>,
-{
-    pub fn new(cache: T) -> Self {
-        Self {
-            cache: RwLock::new(cache),
-            _phantom: PhantomData,
+    fn store(&self, shard: &Shard<K, V>, key: K, value: Arc<V>) {
+        let weight = (self.weigher)(&key, &value);
+        // it would push out everything else and be evicted right after
+        if weight > self.budget {
+            return;
+        }
+        {
+            let mut entries = shard.entries.lock();
+            let entry = Entry {
+                value,
+                weight,
+                created: Instant::now(),
+            };
+            if let Some(replaced) = entries.put(key, entry) {
+                self.weight.fetch_sub(replaced.weight, Ordering::Relaxed);
+            }
+            self.weight.fetch_add(weight, Ordering::Relaxed);
+
+            // new entry is the most recently used one, so it goes last
+            while self.is_over_budget() && entries.len() > 1 {
+                if let Some((_, evicted)) = entries.pop_lru() {
+                    self.weight.fetch_sub(evicted.weight, Ordering::Relaxed);
+                }
+            }
+        }
+
+        // other shards are locked one at a time after releasing this one, so two
+        // inserts can't wait for each other
+        let mut empty = 0;
+        while self.is_over_budget() && empty < SHARDS {
+            let victim = &self.shards[self.next_victim.fetch_add(1, Ordering::Relaxed) % SHARDS];
+            let evicted = if std::ptr::eq(victim, shard) {
+                None
+            } else {
+                victim.entries.lock().pop_lru()
+            };
+            match evicted {
+                Some((_, evicted)) => {
+                    self.weight.fetch_sub(evicted.weight, Ordering::Relaxed);
+                    empty = 0;
+                }
+                None => empty += 1,
+            }
         }
     }
 
-    pub fn content<'a>(&self) -> C {
-        let cache = self.cache.upgradable_read();
-        if let Some(content) = cache.try_read() {
-            content
-        } else {
-            let mut cache = RwLockUpgradableReadGuard::upgrade(cache);
-            cache.read_mut()
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut entries = shard.entries.lock();
+            let removed: usize = entries.iter().map(|(_, entry)| entry.weight).sum();
+            entries.clear();
+            self.weight.fetch_sub(removed, Ordering::Relaxed);
         }
     }
-}
 
-pub struct OptimisticLRU<K, V> {
-    cache: RwLock<lru::LruCache<K, Arc<V>>>,
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            entries: self
+                .shards
+                .iter()
+                .map(|shard| shard.entries.lock().len())
+                .sum(),
+            weight: self.weight.load(Ordering::Relaxed),
+        }
+    }
 }
 
-impl<K, V> OptimisticLRU<K, V>
-where
-    K: std::hash::Hash + Eq,
-{
-    pub fn new(cap: usize) -> Self {
-        Self {
-            cache: RwLock::new(lru::LruCache::new(cap)),
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_single_flight() {
+        let cache = Arc::new(ConcurrentCache::new(100));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    cache.get_or_insert_with(1, |_| {
+                        std::thread::sleep(Duration::from_millis(50));
+                        "value"
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(*thread.join().unwrap(), "value");
         }
+        let stats = cache.stats();
+        assert_eq!(stats.loads, 1);
+        assert_eq!(stats.hits + stats.misses, 8);
     }
 
-    pub fn get_or_update<F>(&self, key: K, update: F) -> Arc<V>
-    where
-        F: FnOnce(&K) -> V,
-    {
-        let cache = self.cache.upgradable_read();
-        if let Some(content) = cache.peek(&key) {
-            content.clone()
-        }else {
-            let mut cache = RwLockUpgradableReadGuard::upgrade(cache);
-            let content = Arc::new((update)(&key));
-            cache.put(key, content.clone());
-            content
+    #[test]
+    fn test_budget() {
+        let cache: ConcurrentCache<u32, usize> = ConcurrentCache::with_budget(100, |_, size| *size);
+        // keys of the same shard are evicted in exact LRU order, other shards in turns
+        let shard_keys: Vec<u32> = {
+            let first = cache.shard(&0);
+            (0..)
+                .filter(|key| std::ptr::eq(cache.shard(key), first))
+                .take(4)
+                .collect()
+        };
+        for key in &shard_keys[..3] {
+            cache.insert(*key, 40);
         }
-    }
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.weight), (2, 80));
+        assert!(cache.get(&shard_keys[0]).is_none());
+
+        assert!(cache.get(&shard_keys[1]).is_some());
+        cache.insert(shard_keys[3], 40);
+        assert!(cache.get(&shard_keys[2]).is_none());
+        assert!(cache.get(&shard_keys[1]).is_some());
+
+        // larger than whole budget, kept out instead of evicting everything
+        assert_eq!(*cache.get_or_insert_with(1001, |_| 150), 150);
+        assert!(cache.get(&1001).is_none());
+        assert_eq!(cache.stats().entries, 2);
+
+        // budget is shared, so value larger than a shard's share still fits
+        for key in 2000..2050 {
+            cache.insert(key, 10);
+            assert!(cache.stats().weight <= 100);
+        }
+        cache.insert(3000, 90);
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.weight), (2, 100));
+        assert!(cache.get(&3000).is_some());
 
-    pub fn clear(&self) {
-        let mut cache = self.cache.write();
         cache.clear();
+        assert_eq!(cache.stats().weight, 0);
+
+        let failed: Result<_, ()> = cache.get_or_try_insert_with(1000, |_| Err(()));
+        assert!(failed.is_err());
+        assert!(cache.get(&1000).is_none());
+    }
+
+    #[test]
+    fn test_budget_near_limit() {
+        // any three entries are just over budget, keys spread over all shards
+        let cache: Arc<ConcurrentCache<u32, usize>> =
+            Arc::new(ConcurrentCache::with_budget(1000, |_, size| *size));
+        for key in 0..100 {
+            cache.insert(key, 334);
+            let stats = cache.stats();
+            assert!(stats.weight <= 1000);
+            assert_eq!(stats.entries, (key as usize + 1).min(2));
+        }
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for key in 0..500 {
+                        cache.insert(thread * 1000 + key, 330 + key as usize % 5);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stats = cache.stats();
+        assert!(stats.weight <= 1000);
+        assert_eq!(
+            stats.weight,
+            cache
+                .shards
+                .iter()
+                .map(|shard| {
+                    let entries = shard.entries.lock();
+                    entries.iter().map(|(_, entry)| entry.weight).sum::<usize>()
+                })
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = ConcurrentCache::new(10).with_ttl(Duration::from_millis(20));
+        cache.insert("key", 1);
+        assert_eq!(cache.get(&"key").as_deref(), Some(&1));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get(&"key").is_none());
+        assert_eq!(*cache.get_or_insert_with("key", |_| 2), 2);
     }
 }
@@ -0,0 +1,418 @@
+//! Activity statistics of channels. Going through every day file takes a while for large
+//! exports, so they are computed by background job whenever export is loaded and kept
+//! until the next reload.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+
+use crate::{
+    export::{dates_in_range, for_each_day},
+    reader::{ChannelInfo, Entry, MessagesReader},
+    simple_cache::ConcurrentCache,
+    READER,
+};
+
+/// Reports kept per set of channels, there is one set for each kind of access
+const CACHED_REPORTS: usize = 64;
+
+/// Activity of one or more channels, in a form which can be merged
+#[derive(Clone, Debug, Default)]
+pub struct Activity {
+    per_day: BTreeMap<NaiveDate, usize>,
+    posters: HashMap<String, usize>,
+    /// Messages by day of week starting with Monday and by hour, in UTC
+    hours_of_week: [[usize; 24]; 7],
+    top_level: usize,
+    replies: usize,
+    /// Seconds from previous message of thread to each reply
+    response_secs: Vec<u32>,
+}
+
+impl Activity {
+    fn add(&mut self, date: NaiveDate, entry: &Entry) {
+        *self.per_day.entry(date).or_default() += 1;
+        *self.posters.entry(entry.user_id.clone()).or_default() += 1;
+        let time = entry.timestamp;
+        self.hours_of_week[time.weekday().num_days_from_monday() as usize][time.hour() as usize] +=
+            1;
+        if entry.is_thread_reply() {
+            self.replies += 1;
+        } else {
+            self.top_level += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Activity) {
+        for (date, count) in &other.per_day {
+            *self.per_day.entry(*date).or_default() += count;
+        }
+        for (user, count) in &other.posters {
+            *self.posters.entry(user.clone()).or_default() += count;
+        }
+        for (day, hours) in other.hours_of_week.iter().enumerate() {
+            for (hour, count) in hours.iter().enumerate() {
+                self.hours_of_week[day][hour] += count;
+            }
+        }
+        self.top_level += other.top_level;
+        self.replies += other.replies;
+        self.response_secs.extend(&other.response_secs);
+    }
+
+    pub fn messages(&self) -> usize {
+        self.top_level + self.replies
+    }
+
+    /// Summary with `top_posters` most active users, named by `user_name`
+    pub fn report(&self, top_posters: usize, user_name: impl Fn(&str) -> String) -> Report {
+        let first_activity = self.per_day.keys().next().copied();
+        let last_activity = self.per_day.keys().next_back().copied();
+
+        // days without messages are counted too, so charts have no gaps
+        let mut per_day = Vec::new();
+        let mut per_week: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+        let mut per_month: BTreeMap<(i32, u32), usize> = BTreeMap::new();
+        if let (Some(first), Some(last)) = (first_activity, last_activity) {
+            let mut date = first;
+            while date <= last {
+                let count = self.per_day.get(&date).copied().unwrap_or_default();
+                per_day.push(Count {
+                    period: date.to_string(),
+                    messages: count,
+                });
+                let week = date - Duration::days(date.weekday().num_days_from_monday().into());
+                *per_week.entry(week).or_default() += count;
+                *per_month.entry((date.year(), date.month())).or_default() += count;
+                date = date.succ();
+            }
+        }
+
+        let mut posters: Vec<_> = self.posters.iter().collect();
+        posters.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let mut response_secs = self.response_secs.clone();
+        response_secs.sort_unstable();
+
+        Report {
+            first_activity,
+            last_activity,
+            messages: self.messages(),
+            top_level: self.top_level,
+            thread_replies: self.replies,
+            thread_ratio: if self.messages() == 0 {
+                0.0
+            } else {
+                self.replies as f64 / self.messages() as f64
+            },
+            median_response_secs: response_secs.get(response_secs.len() / 2).copied(),
+            per_day: Series::new(per_day),
+            per_week: Series::new(
+                per_week
+                    .into_iter()
+                    .map(|(week, messages)| Count {
+                        period: week.to_string(),
+                        messages,
+                    })
+                    .collect(),
+            ),
+            per_month: Series::new(
+                per_month
+                    .into_iter()
+                    .map(|((year, month), messages)| Count {
+                        period: format!("{}-{:02}", year, month),
+                        messages,
+                    })
+                    .collect(),
+            ),
+            top_posters: posters
+                .into_iter()
+                .take(top_posters)
+                .map(|(user_id, messages)| Poster {
+                    user_id: user_id.clone(),
+                    name: user_name(user_id),
+                    messages: *messages,
+                })
+                .collect(),
+            hours_of_week: self.hours_of_week,
+            hours_max: self
+                .hours_of_week
+                .iter()
+                .flat_map(|hours| hours.iter())
+                .copied()
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Count {
+    /// Day, first day of week or month as `YYYY-MM`
+    pub period: String,
+    pub messages: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Series {
+    pub counts: Vec<Count>,
+    /// Highest count, for scaling of charts
+    pub max: usize,
+}
+
+impl Series {
+    fn new(counts: Vec<Count>) -> Self {
+        let max = counts
+            .iter()
+            .map(|count| count.messages)
+            .max()
+            .unwrap_or_default();
+        Self { counts, max }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Poster {
+    pub user_id: String,
+    pub name: String,
+    pub messages: usize,
+}
+
+/// Activity summarized for pages and API
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub first_activity: Option<NaiveDate>,
+    pub last_activity: Option<NaiveDate>,
+    pub messages: usize,
+    pub top_level: usize,
+    pub thread_replies: usize,
+    /// Share of messages posted as thread replies
+    pub thread_ratio: f64,
+    /// Median time to reply in threads
+    pub median_response_secs: Option<u32>,
+    pub per_day: Series,
+    pub per_week: Series,
+    pub per_month: Series,
+    pub top_posters: Vec<Poster>,
+    pub hours_of_week: [[usize; 24]; 7],
+    pub hours_max: usize,
+}
+
+/// Go through all retained days of channel, days that fail to load are skipped. Days
+/// are read past day cache, so pages being viewed are not pushed out of it.
+fn compute_channel(reader: &MessagesReader, channel: &ChannelInfo) -> Activity {
+    let mut activity = Activity::default();
+    // replies can come days after previous message of thread
+    let mut last_in_thread: HashMap<DateTime<Utc>, DateTime<Utc>> = HashMap::new();
+    let walked = dates_in_range(reader, &channel.id, None, None).and_then(|dates| {
+        for_each_day(reader, channel, dates, |date, messages| {
+            let mut entries: Vec<_> = messages.messages.iter().collect();
+            entries.sort_by_key(|entry| entry.timestamp);
+            for entry in entries {
+                activity.add(date, entry);
+                if let Some(thread_ts) = entry.thread_ts {
+                    if entry.is_thread_reply() {
+                        let previous = last_in_thread.get(&thread_ts).copied().unwrap_or(thread_ts);
+                        let secs = (entry.timestamp - previous).num_seconds();
+                        activity.response_secs.push(secs.max(0) as u32);
+                    }
+                    last_in_thread.insert(thread_ts, entry.timestamp);
+                }
+            }
+            Ok(())
+        })
+    });
+    if let Err(err) = walked {
+        log::warn!("Failed to list days of {}: {:#}", channel.name, err);
+    }
+    activity
+}
+
+/// Statistics of all channels of one loaded export
+pub struct ComputedStats {
+    pub computed_at: SystemTime,
+    /// Export statistics were computed from
+    loaded_at: SystemTime,
+    channels: HashMap<String, Activity>,
+    /// Keyed by sorted channel ids and number of top posters, merging and sorting
+    /// response times of large workspace is done once
+    reports: ConcurrentCache<(Vec<String>, usize), Report>,
+}
+
+impl ComputedStats {
+    pub fn channel(&self, channel_id: &str) -> Option<&Activity> {
+        self.channels.get(channel_id)
+    }
+
+    /// Report of given channels together, e.g. all channels viewer can see
+    pub fn report<'a>(
+        &self,
+        channels: impl IntoIterator<Item = &'a ChannelInfo>,
+        top_posters: usize,
+        user_name: impl Fn(&str) -> String,
+    ) -> Arc<Report> {
+        let mut channel_ids: Vec<String> = channels
+            .into_iter()
+            .map(|channel| channel.id.clone())
+            .collect();
+        channel_ids.sort_unstable();
+        channel_ids.dedup();
+        self.reports
+            .get_or_insert_with((channel_ids, top_posters), |(channel_ids, top_posters)| {
+                let mut combined = Activity::default();
+                for channel_id in channel_ids {
+                    if let Some(activity) = self.channel(channel_id) {
+                        combined.merge(activity);
+                    }
+                }
+                combined.report(*top_posters, user_name)
+            })
+    }
+}
+
+lazy_static! {
+    static ref COMPUTED: RwLock<Option<Arc<ComputedStats>>> = RwLock::new(None);
+    /// Export being processed by running job, so it is not started twice
+    static ref RUNNING: Mutex<Option<SystemTime>> = Mutex::new(None);
+}
+
+/// Statistics of export `reader` was loaded from, `None` while they are being computed
+pub fn get(reader: &MessagesReader) -> Option<Arc<ComputedStats>> {
+    let computed = COMPUTED
+        .read()
+        .clone()
+        .filter(|computed| computed.loaded_at == reader.loaded_at());
+    if computed.is_none() {
+        start(READER.current());
+    }
+    computed
+}
+
+/// Compute statistics of `reader` in background, unless it is being done already
+pub fn start(reader: Arc<MessagesReader>) {
+    let loaded_at = reader.loaded_at();
+    {
+        let mut running = RUNNING.lock();
+        if *running == Some(loaded_at) {
+            return;
+        }
+        *running = Some(loaded_at);
+    }
+
+    thread::spawn(move || {
+        let started = Instant::now();
+        // sidebar shows them as soon as they are ready
+        reader.compute_summaries();
+        let mut channels = HashMap::new();
+        for channel in reader.list_channels() {
+            // export was reloaded meanwhile, job for the new one takes over
+            if READER.current().loaded_at() != loaded_at {
+                return;
+            }
+            channels.insert(channel.id.clone(), compute_channel(&reader, channel));
+        }
+        log::info!(
+            "Statistics of {} channels computed in {:.1?}",
+            channels.len(),
+            started.elapsed()
+        );
+
+        if READER.current().loaded_at() == loaded_at {
+            *COMPUTED.write() = Some(Arc::new(ComputedStats {
+                computed_at: SystemTime::now(),
+                loaded_at,
+                channels,
+                reports: ConcurrentCache::new(CACHED_REPORTS),
+            }));
+        }
+        let mut running = RUNNING.lock();
+        if *running == Some(loaded_at) {
+            *running = None;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(ts: &str, thread_ts: Option<&str>, user: &str) -> Entry {
+        let mut json = serde_json::json!({"type": "message", "text": "", "user": user, "ts": ts});
+        if let Some(thread_ts) = thread_ts {
+            json["thread_ts"] = thread_ts.into();
+        }
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let first = NaiveDate::from_ymd(2020, 10, 11);
+        let third = NaiveDate::from_ymd(2020, 10, 13);
+        let mut activity = Activity::default();
+        // Sunday 8:00 UTC
+        activity.add(
+            first,
+            &entry("1602403200.000100", Some("1602403200.000100"), "U1"),
+        );
+        activity.add(
+            first,
+            &entry("1602403260.000100", Some("1602403200.000100"), "U2"),
+        );
+        let mut other = Activity::default();
+        other.add(third, &entry("1602576000.000100", None, "U2"));
+        other.response_secs = vec![60, 10, 600];
+        activity.merge(&other);
+
+        let report = activity.report(1, |id| id.to_string());
+        assert_eq!(report.messages, 3);
+        assert_eq!(report.thread_replies, 1);
+        assert_eq!(report.first_activity, Some(first));
+        assert_eq!(report.last_activity, Some(third));
+        assert_eq!(report.per_day.counts.len(), 3);
+        assert_eq!(report.per_day.counts[1].messages, 0);
+        assert_eq!(report.per_week.counts.len(), 2);
+        assert_eq!(report.median_response_secs, Some(60));
+        assert_eq!(report.top_posters[0].user_id, "U2");
+        assert_eq!(report.hours_of_week[6][8], 2);
+        assert_eq!(report.hours_max, 2);
+    }
+
+    #[test]
+    fn test_compute_channel() {
+        let reader = crate::test_support::reader().with_day_cache(1 << 20);
+        let general = reader.get_channel("C1").unwrap();
+        let activity = compute_channel(&reader, general);
+        assert_eq!((activity.top_level, activity.replies), (2, 2));
+        // late reply is timed from the previous reply, a day later
+        assert_eq!(activity.response_secs, vec![60, 86340]);
+        assert_eq!(activity.per_day.len(), 2);
+        assert_eq!(reader.day_cache_stats().unwrap().entries, 0);
+
+        let random = reader.get_channel("C2").unwrap();
+        let computed = ComputedStats {
+            computed_at: SystemTime::now(),
+            loaded_at: reader.loaded_at(),
+            channels: vec![
+                (general.id.clone(), activity),
+                (random.id.clone(), compute_channel(&reader, random)),
+            ]
+            .into_iter()
+            .collect(),
+            reports: ConcurrentCache::new(CACHED_REPORTS),
+        };
+        let report = computed.report(vec![general, random], 10, |id| id.to_string());
+        assert_eq!(report.messages, 5);
+        assert_eq!(report.median_response_secs, Some(86340));
+        let again = computed.report(vec![random, general], 10, |_| unreachable!());
+        assert!(Arc::ptr_eq(&report, &again));
+        let single = computed.report(Some(random), 10, |id| id.to_string());
+        assert_eq!(single.messages, 1);
+    }
+}
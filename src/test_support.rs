@@ -0,0 +1,99 @@
+//! Small export shared by tests, also set as data path of global config so code going
+//! through `READER` sees it
+
+use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+};
+
+use lazy_static::lazy_static;
+use serde_json::json;
+use tempfile::TempDir;
+
+use crate::{config, reader::MessagesReader};
+
+/// 2020-10-11 08:00 UTC
+pub const PARENT_TS: &str = "1602403200.000100";
+/// Reply to `PARENT_TS` a minute later
+pub const REPLY_TS: &str = "1602403260.000100";
+/// Reply to `PARENT_TS` posted next day, 2020-10-12 08:00 UTC
+pub const LATE_REPLY_TS: &str = "1602489600.000100";
+
+pub fn message(ts: &str, user: &str, text: &str, thread_ts: Option<&str>) -> serde_json::Value {
+    let mut message = json!({"type": "message", "ts": ts, "user": user, "text": text});
+    if let Some(thread_ts) = thread_ts {
+        message["thread_ts"] = thread_ts.into();
+    }
+    message
+}
+
+pub fn write_json(path: &Path, value: &serde_json::Value) {
+    create_dir_all(path.parent().unwrap()).unwrap();
+    write(path, serde_json::to_vec(value).unwrap()).unwrap();
+}
+
+pub fn create_export(path: &Path) {
+    write_json(
+        &path.join("channels.json"),
+        &json!([
+            {"id": "C1", "name": "general", "members": ["U1", "U2"]},
+            {"id": "C2", "name": "random", "members": ["U1"]},
+        ]),
+    );
+    write_json(
+        &path.join("users.json"),
+        &json!([
+            {"id": "U1", "name": "alice", "profile": {"display_name": "Alice", "real_name": "Alice A", "email": "alice@example.com"}},
+            {"id": "U2", "name": "bob", "profile": {"display_name": "", "real_name": "Bob B", "email": "bob@example.com"}},
+        ]),
+    );
+    write_json(
+        &path.join("general/2020-10-11.json"),
+        &json!([
+            message(PARENT_TS, "U1", "Lunch?", Some(PARENT_TS)),
+            message(REPLY_TS, "U2", "Sure", Some(PARENT_TS)),
+            message("1602406800.000100", "U2", "Anyone here?", None),
+        ]),
+    );
+    write_json(
+        &path.join("general/2020-10-12.json"),
+        &json!([message(LATE_REPLY_TS, "U1", "Too late", Some(PARENT_TS))]),
+    );
+    write_json(
+        &path.join("random/2020-10-11.json"),
+        &json!([message("1602405000.000100", "U1", "Random thought", None)]),
+    );
+}
+
+lazy_static! {
+    static ref EXPORT: TempDir = {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("export");
+        create_export(&export);
+        let config_path = dir.path().join("config.toml");
+        write(
+            &config_path,
+            format!(
+                "data_path = {:?}\nday_cache_bytes = 0\n[reload]\nwatch = false\n",
+                export.to_string_lossy()
+            ),
+        )
+        .unwrap();
+        config::init(&config_path).unwrap();
+        dir
+    };
+}
+
+/// Set global config to shared export, for code reading config without export
+pub fn init_config() {
+    lazy_static::initialize(&EXPORT);
+}
+
+/// Directory of shared export, configured as data path on first use
+pub fn export_path() -> std::path::PathBuf {
+    EXPORT.path().join("export")
+}
+
+pub fn reader() -> MessagesReader {
+    MessagesReader::new(export_path()).unwrap()
+}
@@ -0,0 +1,170 @@
+//! Validators for conditional requests. Archive content changes only when export is
+//! reloaded or day file is modified, so pages are revalidated instead of rendered again.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    dev::HttpResponseBuilder,
+    http::header::{self, EntityTag, IfModifiedSince, IfNoneMatch},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+
+use super::{sidebar, static_files, TEMPLATES};
+use crate::{
+    authz::{Access, Viewer},
+    reader::{DayVersion, MessagesReader},
+};
+
+/// Fingerprinted static files are never going to change under the same url
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+lazy_static! {
+    /// Hashes of embedded static files by path relative to `static/`
+    pub static ref STATIC_HASHES: HashMap<String, String> = static_files()
+        .into_iter()
+        .map(|file| {
+            (
+                file.path().to_string_lossy().replace('\\', "/"),
+                short_hash(file.contents()),
+            )
+        })
+        .collect();
+    /// Changes with every build which modifies pages, so cached pages are not reused
+    /// after upgrade
+    static ref BUILD_HASH: String = {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        for file in TEMPLATES.files() {
+            hasher.update(file.path().to_string_lossy().as_bytes());
+            hasher.update(file.contents());
+        }
+        for hash in STATIC_HASHES.values() {
+            hasher.update(hash);
+        }
+        hex(&hasher.finalize())
+    };
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn short_hash(content: &[u8]) -> String {
+    hex(&Sha256::digest(content))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn to_system_time(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
+/// Identifies content of page as seen by one viewer
+pub struct PageVersion {
+    hasher: Sha256,
+    last_modified: SystemTime,
+}
+
+impl PageVersion {
+    /// Page depending on loaded export and who is looking at it
+    pub fn new(req: &HttpRequest, reader: &MessagesReader, viewer: &Viewer) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(BUILD_HASH.as_bytes());
+        hasher.update(req.uri().to_string());
+        hasher.update(format!("{:?}", reader.loaded_at()));
+        if let Some(identity) = &viewer.identity {
+            hasher.update(&identity.username);
+        }
+        hasher.update(match viewer.access {
+            Access::All => "all",
+            Access::Restricted { .. } => "restricted",
+        });
+        // sidebar is part of every page
+        for name in &[sidebar::SORT_COOKIE, sidebar::FAVORITES_COOKIE] {
+            if let Some(cookie) = req.cookie(name) {
+                hasher.update(format!("{}={}", name, cookie.value()));
+            }
+        }
+        Self {
+            hasher,
+            last_modified: reader.loaded_at(),
+        }
+    }
+
+    /// Page listing days of channel, which may appear without reload
+    pub fn with_dates(mut self, dates: &[NaiveDate]) -> Self {
+        for date in dates {
+            self.hasher.update(date.to_string());
+        }
+        self
+    }
+
+    /// Page showing content of day
+    pub fn with_day(mut self, version: DayVersion) -> Self {
+        self.hasher.update(version.mtime.to_le_bytes());
+        self.hasher.update(version.size.to_le_bytes());
+        self.last_modified = self.last_modified.max(to_system_time(version.mtime));
+        self
+    }
+
+    /// Page showing data computed in background at `time`
+    pub fn with_time(mut self, time: SystemTime) -> Self {
+        self.hasher.update(format!("{:?}", time));
+        self.last_modified = self.last_modified.max(time);
+        self
+    }
+
+    pub fn validators(&self) -> (EntityTag, SystemTime) {
+        // weak as compressed responses are not byte-for-byte the same
+        (
+            EntityTag::weak(hex(&self.hasher.clone().finalize())),
+            self.last_modified,
+        )
+    }
+}
+
+/// Whether client has current version according to `If-None-Match`, or
+/// `If-Modified-Since` when there is no etag to compare
+pub fn is_fresh(req: &HttpRequest, etag: &EntityTag, last_modified: Option<SystemTime>) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => match (req.get_header::<IfModifiedSince>(), last_modified) {
+            (Some(IfModifiedSince(since)), Some(last_modified)) => {
+                // header has only second precision
+                unix_secs(last_modified) <= unix_secs(SystemTime::from(since))
+            }
+            _ => false,
+        },
+    }
+}
+
+/// Set validators of page, clients have to revalidate as content depends on login
+pub fn set_page_headers(builder: &mut HttpResponseBuilder, version: &PageVersion) {
+    let (etag, last_modified) = version.validators();
+    builder
+        .set(header::ETag(etag))
+        .set(header::LastModified(last_modified.into()))
+        .header(header::CACHE_CONTROL, "private, no-cache");
+}
+
+/// Response to conditional request for page, `None` when it has to be rendered
+pub fn not_modified(req: &HttpRequest, version: &PageVersion) -> Option<HttpResponse> {
+    let (etag, last_modified) = version.validators();
+    if is_fresh(req, &etag, Some(last_modified)) {
+        let mut builder = HttpResponse::NotModified();
+        set_page_headers(&mut builder, version);
+        Some(builder.finish())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,250 @@
+//! Messages around a timestamp, for permalinks and "show more context" of search hits
+
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::view_day::{render_message_list, Message};
+use super::*;
+use crate::reader::{floating_timestamp, MessageContext};
+
+const DEFAULT_CONTEXT: usize = 10;
+/// Upper bound of messages on each side of anchor
+const MAX_CONTEXT: usize = 200;
+
+#[derive(Deserialize)]
+struct ContextQuery {
+    before: Option<usize>,
+    after: Option<usize>,
+    /// Only messages without layout, replaced in page by `context.js`
+    #[serde(default)]
+    fragment: bool,
+}
+
+impl ContextQuery {
+    fn before(&self) -> usize {
+        self.before.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT)
+    }
+
+    fn after(&self) -> usize {
+        self.after.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT)
+    }
+}
+
+struct LoadedContext<'a> {
+    channel: &'a ChannelInfo,
+    ts: DateTime<Utc>,
+    context: MessageContext,
+    version: caching::PageVersion,
+}
+
+impl LoadedContext<'_> {
+    /// Whether anchor is the message with requested `ts`, not just the next one
+    fn exact(&self) -> bool {
+        self.context
+            .messages
+            .get(self.context.anchor)
+            .map_or(false, |message| message.entry().timestamp == self.ts)
+    }
+}
+
+fn load_context<'a>(
+    req: &HttpRequest,
+    reader: &'a MessagesReader,
+    viewer: &Viewer,
+    channel_id: &str,
+    ts: &str,
+    query: &ContextQuery,
+) -> Result<LoadedContext<'a>> {
+    let channel = reader
+        .get_channel(channel_id)
+        .ok()
+        .filter(|channel| viewer.access.can_view(channel))
+        .ok_or(WebError::ChannelNotFound)?;
+    let ts = floating_timestamp::parse(ts)
+        .map_err(|_| WebError::BadRequest(format!("Invalid timestamp {}", ts)))?;
+    let context = reader
+        .message_context(channel_id, ts, query.before(), query.after())
+        .map_err(WebError::CorruptData)?;
+    if context.messages.is_empty() {
+        return Err(WebError::DateNotFound);
+    }
+
+    let mut dates: Vec<_> = context
+        .messages
+        .iter()
+        .map(|message| message.date)
+        .collect();
+    dates.dedup();
+    let mut version = caching::PageVersion::new(req, reader, viewer);
+    for date in &dates {
+        version = version.with_day(
+            reader
+                .day_version(channel_id, date)
+                .map_err(WebError::CorruptData)?,
+        );
+    }
+    Ok(LoadedContext {
+        channel,
+        ts,
+        context,
+        version,
+    })
+}
+
+#[derive(Serialize)]
+struct ApiMessage<'a> {
+    ts: String,
+    date: NaiveDate,
+    user_id: &'a str,
+    user_name: Option<&'a str>,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_ts: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiContext<'a> {
+    channel_id: &'a str,
+    channel_name: &'a str,
+    /// Position of requested message in `messages`
+    anchor: usize,
+    exact: bool,
+    messages: Vec<ApiMessage<'a>>,
+}
+
+#[get("/api/{channel}/context/{ts}")]
+async fn api_context(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    parts: web::Path<(String, String)>,
+    query: web::Query<ContextQuery>,
+) -> Result<HttpResponse> {
+    let (channel_id, ts) = parts.into_inner();
+    let loaded = load_context(&req, &reader, &viewer, &channel_id, &ts, &query)?;
+    if let Some(response) = caching::not_modified(&req, &loaded.version) {
+        return Ok(response);
+    }
+
+    let body = ApiContext {
+        channel_id: &loaded.channel.id,
+        channel_name: &loaded.channel.name,
+        anchor: loaded.context.anchor,
+        exact: loaded.exact(),
+        messages: loaded
+            .context
+            .messages
+            .iter()
+            .map(|message| {
+                let entry = message.entry();
+                ApiMessage {
+                    ts: floating_timestamp::to_slack_ts(&entry.timestamp),
+                    date: message.date,
+                    user_id: &entry.user_id,
+                    user_name: reader
+                        .get_user_info(&entry.user_id)
+                        .ok()
+                        .map(|user| user.display_name()),
+                    text: &entry.text,
+                    thread_ts: entry
+                        .thread_ts
+                        .as_ref()
+                        .map(floating_timestamp::to_slack_ts),
+                }
+            })
+            .collect(),
+    };
+    let mut builder = HttpResponse::Ok();
+    caching::set_page_headers(&mut builder, &loaded.version);
+    Ok(builder.json(body))
+}
+
+#[derive(Serialize)]
+struct Day {
+    date: NaiveDate,
+    messages: String,
+}
+
+#[derive(Serialize)]
+struct ContextFragment<'a> {
+    channel_id: &'a str,
+    /// Fragment is rendered alone too, without layout
+    links: Links,
+    /// Messages split by days they were posted on
+    days: Vec<Day>,
+    /// Limits for "show more context", `None` when there is nothing more to show
+    more: Option<(usize, usize)>,
+}
+
+#[derive(Serialize)]
+struct ContextPage<'a> {
+    layout: LayoutContext<'a>,
+    channel: &'a ChannelInfo,
+    ts: &'a str,
+    #[serde(flatten)]
+    fragment: ContextFragment<'a>,
+}
+
+fn render_days(loaded: &LoadedContext<'_>) -> anyhow::Result<Vec<Day>> {
+    let mut days: Vec<(NaiveDate, Vec<Message<'_>>)> = Vec::new();
+    for (position, message) in loaded.context.messages.iter().enumerate() {
+        let mut rendered = Message::new(message.entry()).in_channel(&loaded.channel.id);
+        if position == loaded.context.anchor {
+            rendered = rendered.highlighted();
+        }
+        match days.last_mut() {
+            Some((date, messages)) if *date == message.date => messages.push(rendered),
+            _ => days.push((message.date, vec![rendered])),
+        }
+    }
+    days.into_iter()
+        .map(|(date, messages)| {
+            Ok(Day {
+                date,
+                messages: render_message_list(messages, &Links::server())?,
+            })
+        })
+        .collect()
+}
+
+/// Permalink of message, also serves the messages alone for "show more context"
+#[get("/{channel}/p/{ts}")]
+async fn view_context(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    parts: web::Path<(String, String)>,
+    query: web::Query<ContextQuery>,
+) -> Result<HttpResponse> {
+    let (channel_id, ts) = parts.into_inner();
+    let loaded = load_context(&req, &reader, &viewer, &channel_id, &ts, &query)?;
+    if let Some(response) = caching::not_modified(&req, &loaded.version) {
+        return Ok(response);
+    }
+
+    // history ran out on a side when it has fewer messages than requested
+    let (before, after) = (query.before(), query.after());
+    let preceding = loaded.context.anchor;
+    let following = loaded.context.messages.len() - preceding;
+    let more = Some(((before * 2).min(MAX_CONTEXT), (after * 2).min(MAX_CONTEXT))).filter(|_| {
+        (preceding == before && before < MAX_CONTEXT) || (following > after && after < MAX_CONTEXT)
+    });
+    let fragment = ContextFragment {
+        channel_id: &channel_id,
+        links: Links::server(),
+        days: render_days(&loaded).map_err(WebError::Template)?,
+        more,
+    };
+    if query.fragment {
+        return render_response("context_messages.tera", &fragment, &loaded.version);
+    }
+    let context = ContextPage {
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+        channel: loaded.channel,
+        ts: &ts,
+        fragment,
+    };
+    render_response("context.tera", &context, &loaded.version)
+}
@@ -0,0 +1,233 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use actix_web::{get, http::header, web::Bytes, HttpResponse};
+use chrono::NaiveDate;
+use futures::{channel::mpsc, SinkExt};
+use serde::Deserialize;
+
+use crate::{
+    error::{Result, WebError},
+    export::{
+        flat::{self, FlatFormat},
+        mbox,
+        text::{self, TextFormat},
+        ExportFilter,
+    },
+};
+
+use super::*;
+
+/// Rendered document is sent to client in pieces of about this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+}
+
+/// Exporters usable for single channel download, workspace migration formats are left
+/// to the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    Text(TextFormat),
+    Flat(FlatFormat),
+    Mbox,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Text(format) => format.extension(),
+            ExportFormat::Flat(FlatFormat::Csv) => "csv",
+            ExportFormat::Flat(FlatFormat::JsonLines) => "jsonl",
+            ExportFormat::Mbox => "mbox",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Text(format) => format.content_type(),
+            ExportFormat::Flat(FlatFormat::Csv) => "text/csv; charset=utf-8",
+            ExportFormat::Flat(FlatFormat::JsonLines) => "application/x-ndjson",
+            ExportFormat::Mbox => "application/mbox",
+        }
+    }
+
+    fn render(
+        self,
+        out: &mut impl Write,
+        reader: &MessagesReader,
+        channel_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> anyhow::Result<()> {
+        let filter = ExportFilter {
+            channels: vec![channel_id.to_string()],
+            from: Some(from),
+            to: Some(to),
+            ..ExportFilter::default()
+        };
+        match self {
+            ExportFormat::Text(format) => out.write_all(
+                text::render_document(reader, channel_id, Some(from), Some(to), format)?.as_bytes(),
+            )?,
+            ExportFormat::Flat(format) => flat::write_flat(&mut *out, reader, &filter, format)?,
+            ExportFormat::Mbox => mbox::write_mbox(out, reader, &filter, None)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mbox" => Ok(ExportFormat::Mbox),
+            _ => s
+                .parse()
+                .map(ExportFormat::Text)
+                .or_else(|_| s.parse().map(ExportFormat::Flat))
+                .map_err(|_| format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+/// Passes written bytes to streamed response body, blocks while client is behind
+struct BodyWriter {
+    chunks: mpsc::Sender<Result<Bytes, WebError>>,
+    buffer: Vec<u8>,
+}
+
+impl BodyWriter {
+    fn send(&mut self, chunk: Result<Bytes, WebError>) -> io::Result<()> {
+        futures::executor::block_on(self.chunks.send(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client went away"))
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.send(Ok(chunk))
+    }
+}
+
+#[get("/{channel}/export/{from}/{to}")]
+async fn export_channel(
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    parts: web::Path<(String, NaiveDate, NaiveDate)>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let (channel_id, from, to) = parts.into_inner();
+    let format: ExportFormat = query
+        .format
+        .as_deref()
+        .unwrap_or("markdown")
+        .parse()
+        .map_err(WebError::BadRequest)?;
+    let channel_name = reader
+        .get_channel_name(&channel_id)
+        .ok()
+        .filter(|_| viewer.access.can_view_id(&reader, &channel_id))
+        .ok_or(WebError::ChannelNotFound)?;
+
+    // rendered on blocking pool, failure after first chunk can only abort the download
+    let (chunks, body) = mpsc::channel(4);
+    let reader = reader.shared();
+    let render = web::block(move || -> Result<(), ()> {
+        let mut out = BodyWriter {
+            chunks,
+            buffer: Vec::new(),
+        };
+        if let Err(err) = format.render(&mut out, &reader, &channel_id, from, to) {
+            log::warn!("Export of {} failed: {:#}", channel_id, err);
+            let _ = out.send(Err(WebError::CorruptData(err)));
+        }
+        Ok(())
+    });
+    actix_web::rt::spawn(async move {
+        let _ = render.await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}_{}_{}.{}\"",
+                channel_name,
+                from,
+                to,
+                format.extension()
+            ),
+        )
+        .streaming(body))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::test_support;
+
+    #[actix_rt::test]
+    async fn test_export_channel() {
+        test_support::init_config();
+        let mut app = test::init_service(App::new().service(export_channel)).await;
+        let req = test::TestRequest::get()
+            .uri("/C1/export/2020-10-11/2020-10-12?format=csv")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"general_2020-10-11_2020-10-12.csv\""
+        );
+        let body = test::read_body(response).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.lines().count(), 5);
+        assert!(body.contains("Too late"));
+
+        let req = test::TestRequest::get()
+            .uri("/C1/export/2020-10-11/2020-10-12?format=pdf")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_body_writer_chunks() {
+        let (chunks, body) = mpsc::channel(16);
+        let mut out = BodyWriter {
+            chunks,
+            buffer: Vec::new(),
+        };
+        out.write_all(&vec![b'x'; CHUNK_SIZE + 1]).unwrap();
+        out.write_all(b"end").unwrap();
+        out.flush().unwrap();
+        drop(out);
+
+        let sizes: Vec<_> = futures::executor::block_on_stream(body)
+            .map(|chunk| chunk.unwrap().len())
+            .collect();
+        assert_eq!(sizes, vec![CHUNK_SIZE + 1, 3]);
+    }
+}
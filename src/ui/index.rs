@@ -1,4 +1,4 @@
-use actix_web::{get, HttpResponse};
+use actix_web::{get, HttpRequest, HttpResponse};
 use serde::Serialize;
 
 use super::*;
@@ -6,12 +6,33 @@ use super::*;
 #[derive(Serialize)]
 struct IndexContext<'a> {
     layout: LayoutContext<'a>,
+    search_available: bool,
 }
 
 #[get("/")]
-async fn index(reader: DataMessagesReader) -> HttpResponse {
+async fn index(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+) -> Result<HttpResponse> {
+    let version = caching::PageVersion::new(&req, &reader, &viewer);
+    if let Some(response) = caching::not_modified(&req, &version) {
+        return Ok(response);
+    }
     let context = IndexContext {
-        layout: layout_context(&reader),
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+        search_available: false,
     };
-    render_response("index.tera", &context)
+    render_response("index.tera", &context, &version)
+}
+
+pub fn render_static(reader: &MessagesReader, links: Links) -> anyhow::Result<String> {
+    render_page(
+        "index.tera",
+        &IndexContext {
+            layout: layout_context(reader, &Viewer::unrestricted()).with_links(links),
+            search_available: true,
+        },
+    )
 }
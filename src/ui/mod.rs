@@ -1,32 +1,46 @@
-mod index;
-mod select_date;
-mod view_day;
+mod caching;
+mod context;
+mod export;
+pub mod index;
+pub mod range;
+pub mod select_date;
+pub mod sidebar;
+pub mod stats;
+pub mod thread;
+pub mod timeline;
+pub mod user;
+pub mod view_day;
 
 use super::DataMessagesReader;
 use crate::{
+    authz::Viewer,
+    error::{Result, WebError},
+    metrics,
     reader::{ChannelInfo, MessagesReader},
-    simple_cache::{OptimisticLockCache, TimeCache},
+    simple_cache::{CacheStats, ConcurrentCache},
 };
-use crate::{simple_cache::OptimisticLRU, READER};
+use crate::READER;
+use sidebar::SidebarPrefs;
 use actix_web::{
     get,
+    http::header,
     web::{self},
     HttpRequest, HttpResponse, Scope,
 };
-use chrono::NaiveDate;
 use include_dir::{include_dir, Dir};
 use lazy_static::lazy_static;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::SystemTime};
 use tera::{Context, Error as TeraError, Result as TeraResult, Tera, Value};
 
 static TEMPLATES: Dir = include_dir!("templates");
 static STATIC: Dir = include_dir!("static");
 
 lazy_static! {
-    static ref USERNAME_CACHE: OptimisticLRU<String, String> = OptimisticLRU::new(256);
-    static ref CHANNELS_LIST_CACHE: OptimisticLRU<Option<NaiveDate>, String> =
-        OptimisticLRU::new(256);
+    /// Keyed by load time of reader too, so requests still using replaced reader
+    /// can't fill it with stale names for the new one
+    static ref USERNAME_CACHE: ConcurrentCache<(SystemTime, String), String> =
+        ConcurrentCache::new(256);
     pub static ref TPL: Tera = {
         let mut tera = Tera::default();
         tera.add_raw_templates(
@@ -38,43 +52,60 @@ lazy_static! {
         .unwrap();
         tera.autoescape_on(vec![".tera"]);
         tera.register_function("render_username", RenderUsername);
-        tera.register_function("render_channels", RenderChannels);
+        tera.register_function("render_channels", sidebar::RenderChannels);
+        tera.register_function("static_url", StaticUrl);
         tera
     };
 }
 
-#[derive(Serialize)]
-pub struct ChannelsContext<'a> {
-    channels: Vec<&'a ChannelInfo>,
-    date: &'a Option<NaiveDate>,
+/// Shape of links between pages, live server uses absolute paths while static export
+/// needs relative links to files
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Links {
+    root: String,
+    channel_index: String,
+    page_ext: String,
+}
+
+impl Links {
+    pub fn server() -> Self {
+        Self {
+            root: "/".to_string(),
+            channel_index: String::new(),
+            page_ext: String::new(),
+        }
+    }
+
+    /// Links for page located `depth` directories below export root
+    pub fn static_export(depth: usize) -> Self {
+        Self {
+            root: "../".repeat(depth),
+            channel_index: "/index.html".to_string(),
+            page_ext: ".html".to_string(),
+        }
+    }
 }
 
-struct RenderChannels;
+/// Path of static file with its hash, so it can be cached forever
+struct StaticUrl;
 
-impl tera::Function for RenderChannels {
+impl tera::Function for StaticUrl {
     fn call(&self, args: &HashMap<String, Value>) -> TeraResult<Value> {
-        let date = args
-            .get("date")
-            .map(|v| serde_json::from_value(v.to_owned()).unwrap());
-        Ok(serde_json::to_value(
-            CHANNELS_LIST_CACHE
-                .get_or_update(date, |date| {
-                    let context = Context::from_serialize(ChannelsContext {
-                        channels: READER.list_channels(),
-                        date,
-                    })
-                    .unwrap();
-                    TPL.render("channels.tera", &context).unwrap()
-                })
-                .as_ref(),
-        )
-        .unwrap())
+        let path = match args.get("path").and_then(Value::as_str) {
+            Some(path) => path,
+            None => return Err(TeraError::msg("Function `static_url` require `path`")),
+        };
+        let hash = caching::STATIC_HASHES
+            .get(path)
+            .ok_or_else(|| TeraError::msg(format!("Static file `{}` does not exist", path)))?;
+        Ok(Value::String(format!("static/{}?v={}", path, hash)))
     }
 
     fn is_safe(&self) -> bool {
         true
     }
 }
+
 struct RenderUsername;
 
 
@@ -96,19 +127,15 @@ impl tera::Function for RenderUsername {
             }
         };
 
-        Ok(serde_json::to_value(
-            USERNAME_CACHE
-                .get_or_update(user_id, |user_id| {
-                    if let Ok(user_info) = READER.get_user_info(user_id) {
-                        TPL.render("username.tera", &Context::from_serialize(user_info).unwrap())
-                            .unwrap()
-                    } else {
-                        "Unknown".to_string()
-                    }
-                })
-                .as_ref(),
-        )
-        .unwrap())
+        let reader = READER.current();
+        let rendered = USERNAME_CACHE.get_or_try_insert_with(
+            (reader.loaded_at(), user_id),
+            |(_, user_id)| match reader.get_user_info(user_id) {
+                Ok(user_info) => TPL.render("username.tera", &Context::from_serialize(user_info)?),
+                Err(_) => Ok("Unknown".to_string()),
+            },
+        )?;
+        Ok(Value::String(rendered.as_ref().clone()))
     }
 
     fn is_safe(&self) -> bool {
@@ -116,66 +143,176 @@ impl tera::Function for RenderUsername {
     }
 }
 
+pub fn cache_stats() -> Vec<(&'static str, CacheStats)> {
+    vec![
+        ("username", USERNAME_CACHE.stats()),
+        ("channels_list", sidebar::CHANNELS_LIST_CACHE.stats()),
+        ("sidebar", sidebar::SIDEBAR_CACHE.stats()),
+    ]
+}
+
+/// Drop rendered fragments, called when reader is replaced to free memory, entries
+/// of previous reader are never looked up again
+pub fn clear_caches() {
+    USERNAME_CACHE.clear();
+    sidebar::CHANNELS_LIST_CACHE.clear();
+    sidebar::SIDEBAR_CACHE.clear();
+}
+
 #[derive(Serialize)]
 pub struct LayoutContext<'a> {
-    channels: Vec<&'a ChannelInfo>,
+    /// Sorted ids of channels viewer can see, sidebar is rendered from the reader
+    channels: Vec<&'a str>,
+    links: Links,
+    user: Option<String>,
+    sidebar: SidebarPrefs,
+}
+
+impl<'a> LayoutContext<'a> {
+    /// Layout without any archive content, e.g. for login page
+    pub fn empty() -> Self {
+        Self {
+            channels: Vec::new(),
+            links: Links::server(),
+            user: None,
+            sidebar: SidebarPrefs::default(),
+        }
+    }
+
+    pub fn with_links(mut self, links: Links) -> Self {
+        self.links = links;
+        self
+    }
+
+    pub fn with_sidebar(mut self, sidebar: SidebarPrefs) -> Self {
+        self.sidebar = sidebar;
+        self
+    }
 }
 
-pub fn layout_context<'a>(reader: &'a MessagesReader) -> LayoutContext<'a> {
+pub fn layout_context<'a>(reader: &'a MessagesReader, viewer: &Viewer) -> LayoutContext<'a> {
+    let mut channels: Vec<_> = viewer
+        .access
+        .visible_channels(reader)
+        .into_iter()
+        .map(|channel| channel.id.as_str())
+        .collect();
+    channels.sort_unstable();
     LayoutContext {
-        channels: reader.list_channels(),
+        channels,
+        links: Links::server(),
+        user: viewer
+            .identity
+            .as_ref()
+            .map(|identity| identity.username.clone()),
+        sidebar: SidebarPrefs::default(),
     }
 }
 
+/// All embedded static assets with paths relative to `static/`
+pub fn static_files() -> Vec<&'static include_dir::File<'static>> {
+    fn collect(dir: &'static Dir<'static>, files: &mut Vec<&'static include_dir::File<'static>>) {
+        files.extend(dir.files());
+        for dir in dir.dirs() {
+            collect(dir, files);
+        }
+    }
+
+    let mut files = Vec::new();
+    collect(&STATIC, &mut files);
+    files
+}
+
 fn content_type_for_ext(ext: &str) -> &str {
     match ext {
         "css" => "text/css",
+        "js" => "application/javascript",
         _ => "application/octet-stream",
     }
 }
 
+#[derive(Deserialize)]
+struct StaticQuery {
+    /// Hash of file added by `static_url`
+    v: Option<String>,
+}
+
 #[get("/static/{file:.*}")]
-async fn serve_static(req: HttpRequest) -> HttpResponse {
+async fn serve_static(req: HttpRequest, query: web::Query<StaticQuery>) -> HttpResponse {
     let file_name = req.match_info().query("file");
-    let file = STATIC.get_file(file_name);
-    if let Some(file) = file {
-        HttpResponse::Ok()
-            .content_type(content_type_for_ext(
-                &file
-                    .path()
-                    .extension()
-                    .unwrap_or_default()
-                    .to_string_lossy(),
-            ))
-            .body(file.contents())
+    let (file, hash) = match (
+        STATIC.get_file(file_name),
+        caching::STATIC_HASHES.get(file_name),
+    ) {
+        (Some(file), Some(hash)) => (file, hash),
+        _ => return HttpResponse::NotFound().body("File not found"),
+    };
+
+    let etag = header::EntityTag::weak(hash.clone());
+    let cache_control = if query.v.as_ref() == Some(hash) {
+        caching::IMMUTABLE
     } else {
-        HttpResponse::NotFound().body("File not found")
+        "no-cache"
+    };
+    if caching::is_fresh(&req, &etag, None) {
+        return HttpResponse::NotModified()
+            .set(header::ETag(etag))
+            .header(header::CACHE_CONTROL, cache_control)
+            .finish();
     }
+    HttpResponse::Ok()
+        .content_type(content_type_for_ext(
+            &file
+                .path()
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy(),
+        ))
+        .set(header::ETag(etag))
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(file.contents())
 }
 
-pub fn render_page_not_found() -> HttpResponse {
-    HttpResponse::NotFound().body("Page not found")
+pub fn render_page<C>(template_name: &str, data: &C) -> anyhow::Result<String>
+where
+    C: Serialize,
+{
+    let context = Context::from_serialize(data)?;
+    let _timer = metrics::TEMPLATE_RENDER
+        .with_label_values(&[template_name])
+        .start_timer();
+    Ok(TPL.render(template_name, &context)?)
 }
 
-fn render_response<C>(template_name: &str, data: &C) -> HttpResponse
+fn render_response<C>(
+    template_name: &str,
+    data: &C,
+    version: &caching::PageVersion,
+) -> Result<HttpResponse>
 where
     C: Serialize,
 {
-    let context = Context::from_serialize(data).unwrap();
-    let result = TPL.render(template_name, &context);
-    match result {
-        Ok(data) => HttpResponse::Ok().content_type("text/html").body(data),
-        Err(err) => {
-            log::error!("Failed to render page: {:#}", err);
-            HttpResponse::InternalServerError().body("Failed to render the page")
-        }
-    }
+    let page = render_page(template_name, data).map_err(WebError::Template)?;
+    let mut builder = HttpResponse::Ok();
+    caching::set_page_headers(&mut builder, version);
+    Ok(builder.content_type("text/html").body(page))
 }
 
 pub fn routes() -> Scope {
     web::scope("")
         .service(serve_static)
         .service(index::index)
+        // before `select_date` and `view_day`, which would take them for channels
+        .service(stats::workspace_stats)
+        .service(stats::api_workspace_stats)
+        .service(stats::api_channel_stats)
+        .service(stats::channel_stats)
         .service(select_date::select_date)
+        .service(export::export_channel)
+        .service(range::view_range)
+        .service(context::api_context)
+        .service(context::view_context)
+        // before `view_day`, which would take `day` for channel id
+        .service(timeline::timeline)
         .service(view_day::view_day)
 }
@@ -0,0 +1,167 @@
+//! Channel history between two days, loaded page by page so long ranges are parsed only
+//! as far as they are read
+
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use super::view_day::render_messages;
+use super::*;
+use crate::export::dates_in_range;
+
+/// Page ends with first day reaching this many messages
+const PAGE_MESSAGES: usize = 500;
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    /// First day of page, next page starts where previous one ended
+    start: Option<NaiveDate>,
+    /// Only days of page without layout, appended to page by `range.js`
+    #[serde(default)]
+    fragment: bool,
+}
+
+#[derive(Serialize)]
+struct Day {
+    date: NaiveDate,
+    messages: String,
+}
+
+#[derive(Serialize)]
+struct RangePage<'a> {
+    channel_id: &'a str,
+    /// Fragment is rendered alone too, without layout
+    links: Links,
+    days: Vec<Day>,
+    next: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct RangeContext<'a> {
+    layout: LayoutContext<'a>,
+    channel: &'a ChannelInfo,
+    from: NaiveDate,
+    to: NaiveDate,
+    /// Days with history in whole range
+    total_days: usize,
+    #[serde(flatten)]
+    page: RangePage<'a>,
+}
+
+#[get("/{channel}/range/{from}/{to}")]
+async fn view_range(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    parts: web::Path<(String, NaiveDate, NaiveDate)>,
+    query: web::Query<RangeQuery>,
+) -> Result<HttpResponse> {
+    let (channel_id, from, to) = parts.into_inner();
+    let channel = reader
+        .get_channel(&channel_id)
+        .ok()
+        .filter(|channel| viewer.access.can_view(channel))
+        .ok_or(WebError::ChannelNotFound)?;
+    if from > to {
+        return Err(WebError::BadRequest(
+            "Range has to start before it ends".to_string(),
+        ));
+    }
+    // listing days is cheap, only days of page are parsed
+    let dates = dates_in_range(&reader, &channel_id, Some(from), Some(to))
+        .map_err(WebError::CorruptData)?;
+    let start = query.start.unwrap_or(from);
+    let remaining: Vec<_> = dates.iter().filter(|date| **date >= start).collect();
+
+    // page is delimited by message counts, which are kept per version of day, so
+    // unchanged pages are answered without reading any day after they were counted
+    let mut version = caching::PageVersion::new(&req, &reader, &viewer).with_dates(&dates);
+    let mut page_dates = Vec::new();
+    let mut count = 0;
+    for date in &remaining {
+        if count >= PAGE_MESSAGES {
+            break;
+        }
+        let day_version = reader
+            .day_version(&channel_id, date)
+            .map_err(WebError::CorruptData)?;
+        count += reader
+            .day_message_count(&channel_id, date)
+            .map_err(WebError::CorruptData)?;
+        version = version.with_day(day_version);
+        page_dates.push(**date);
+    }
+    let next = remaining.get(page_dates.len()).map(|date| **date);
+    if let Some(response) = caching::not_modified(&req, &version) {
+        return Ok(response);
+    }
+
+    let loaded = page_dates
+        .into_iter()
+        .map(|date| {
+            reader
+                .channel_messages_parse(&channel_id, &date)
+                .map(|messages| (date, messages))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(WebError::CorruptData)?;
+    let days = loaded
+        .iter()
+        .map(|(date, messages)| {
+            Ok(Day {
+                date: *date,
+                messages: render_messages(messages, &channel_id, &Links::server())?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()
+        .map_err(WebError::Template)?;
+    let page = RangePage {
+        channel_id: &channel_id,
+        links: Links::server(),
+        days,
+        next,
+    };
+    if query.fragment {
+        return render_response("range_days.tera", &page, &version);
+    }
+    let context = RangeContext {
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+        channel,
+        from,
+        to,
+        total_days: dates.len(),
+        page,
+    };
+    render_response("range.tera", &context, &version)
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::test_support;
+
+    #[actix_rt::test]
+    async fn test_not_modified() {
+        test_support::init_config();
+        let mut app = test::init_service(App::new().service(view_range)).await;
+        let uri = "/C1/range/2020-10-01/2020-10-31?fragment=true";
+        let response =
+            test::call_service(&mut app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+        let body = test::read_body(response).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Lunch?") && body.contains("Too late"));
+        assert!(body.contains(&format!("href=\"&#x2F;C1/p/{}\"", test_support::PARENT_TS)));
+
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .header(header::IF_NONE_MATCH, etag)
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
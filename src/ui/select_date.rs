@@ -1,30 +1,100 @@
-use actix_web::{get, HttpResponse};
+use actix_web::{get, HttpRequest, HttpResponse};
 use chrono::NaiveDate;
 use serde::Serialize;
 
-use crate::error::WebError;
-
 use super::*;
 
 #[derive(Serialize)]
 struct SelectDateContext<'a> {
+    layout: LayoutContext<'a>,
     dates_available: Vec<NaiveDate>,
     channel_id: &'a str,
 }
 
+/// Channels listed in export without folder have no history, other failures mean
+/// export is damaged
+fn channel_dates(reader: &MessagesReader, channel_id: &str) -> Result<Vec<NaiveDate>> {
+    match reader.list_dates(channel_id) {
+        Ok(dates) => Ok(dates),
+        Err(err)
+            if err
+                .downcast_ref::<std::io::Error>()
+                .map_or(false, |err| err.kind() == std::io::ErrorKind::NotFound) =>
+        {
+            Ok(Vec::new())
+        }
+        Err(err) => Err(WebError::CorruptData(err)),
+    }
+}
+
 #[get("/{channel}")]
-async fn select_date(reader: DataMessagesReader, parts: web::Path<(String,)>) -> HttpResponse {
+async fn select_date(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    parts: web::Path<(String,)>,
+) -> Result<HttpResponse> {
     let channel_id = parts.into_inner().0;
-    let context: Result<_, WebError> = (|| {
-        Ok(SelectDateContext {
-            dates_available: reader.list_dates(&channel_id)?,
-            channel_id: &channel_id,
-        })
-    })();
-
-    if let Ok(context) = context {
-        render_response("select_date.tera", &context)
-    } else {
-        render_page_not_found()
+    if !viewer.access.can_view_id(&reader, &channel_id) {
+        return Err(WebError::ChannelNotFound);
+    }
+    let dates_available = channel_dates(&reader, &channel_id)?;
+    let version = caching::PageVersion::new(&req, &reader, &viewer).with_dates(&dates_available);
+    if let Some(response) = caching::not_modified(&req, &version) {
+        return Ok(response);
+    }
+
+    let context = SelectDateContext {
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+        dates_available,
+        channel_id: &channel_id,
+    };
+    render_response("select_date.tera", &context, &version)
+}
+
+pub fn render_static(
+    reader: &MessagesReader,
+    channel_id: &str,
+    dates_available: Vec<NaiveDate>,
+    links: Links,
+) -> anyhow::Result<String> {
+    render_page(
+        "select_date.tera",
+        &SelectDateContext {
+            layout: layout_context(reader, &Viewer::unrestricted()).with_links(links),
+            dates_available,
+            channel_id,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_channel_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        test_support::create_export(dir.path());
+        test_support::write_json(
+            &dir.path().join("channels.json"),
+            &serde_json::json!([
+                {"id": "C1", "name": "general"},
+                {"id": "C3", "name": "quiet"},
+                {"id": "C4", "name": "broken"},
+            ]),
+        );
+        // folder replaced by file can't be listed
+        std::fs::write(dir.path().join("broken"), "").unwrap();
+        let reader = MessagesReader::new(dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(channel_dates(&reader, "C1").unwrap().len(), 2);
+        assert!(channel_dates(&reader, "C3").unwrap().is_empty());
+        assert!(matches!(
+            channel_dates(&reader, "C4"),
+            Err(WebError::CorruptData(_))
+        ));
     }
 }
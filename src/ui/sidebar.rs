@@ -0,0 +1,373 @@
+//! Channel list next to every page. Groups of channels are rendered once per set of
+//! visible channels and cached, favorites of viewer are added around them and whole
+//! list is cached again per set of favorites.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use chrono::NaiveDate;
+use futures::future::{ok, Ready};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tera::{Context, Error as TeraError, Result as TeraResult, Value};
+
+use super::{Links, TPL};
+use crate::{
+    error::WebError,
+    reader::{ChannelInfo, ChannelSummary, MessagesReader},
+    simple_cache::ConcurrentCache,
+    READER,
+};
+
+pub const SORT_COOKIE: &str = "sidebar_sort";
+/// Channel ids separated with `.`
+pub const FAVORITES_COOKIE: &str = "sidebar_favorites";
+
+/// Load time of reader, links, selected day, visible channel ids and sort order
+type GroupsKey = (SystemTime, Links, Option<NaiveDate>, Vec<String>, SortOrder);
+/// Groups and sorted ids of visible favorites
+type SidebarKey = (GroupsKey, Vec<String>);
+
+lazy_static! {
+    pub static ref CHANNELS_LIST_CACHE: ConcurrentCache<GroupsKey, String> =
+        ConcurrentCache::new(256);
+    pub static ref SIDEBAR_CACHE: ConcurrentCache<SidebarKey, String> = ConcurrentCache::new(1024);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Name,
+    /// Most recently active first
+    Activity,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self::Name
+    }
+}
+
+/// Sidebar settings of viewer, kept in cookies
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SidebarPrefs {
+    pub sort: SortOrder,
+    pub favorites: Vec<String>,
+}
+
+impl FromRequest for SidebarPrefs {
+    type Config = ();
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let sort = match req.cookie(SORT_COOKIE) {
+            Some(cookie) if cookie.value() == "activity" => SortOrder::Activity,
+            _ => SortOrder::Name,
+        };
+        let favorites = req
+            .cookie(FAVORITES_COOKIE)
+            .map(|cookie| {
+                cookie
+                    .value()
+                    .split('.')
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        ok(Self { sort, favorites })
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ChannelEntry<'a> {
+    id: &'a str,
+    name: &'a str,
+    /// Not shown until summaries are computed
+    #[serde(flatten)]
+    summary: Option<ChannelSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChannelGroup<'a> {
+    /// Shared prefix, `None` for channels without group
+    name: Option<&'a str>,
+    channels: Vec<ChannelEntry<'a>>,
+}
+
+/// Part of name before first `-` or `_`, like `team` in `team-backend`
+fn name_prefix(name: &str) -> Option<&str> {
+    name.find(&['-', '_'][..])
+        .filter(|&end| end > 0 && end + 1 < name.len())
+        .map(|end| &name[..end])
+}
+
+fn last_activity(entry: &ChannelEntry<'_>) -> Option<NaiveDate> {
+    entry
+        .summary
+        .as_ref()
+        .and_then(|summary| summary.last_activity)
+}
+
+/// Channels sharing name prefix are grouped when there is more than one of them
+fn group_channels(mut channels: Vec<ChannelEntry<'_>>, sort: SortOrder) -> Vec<ChannelGroup<'_>> {
+    match sort {
+        SortOrder::Name => channels.sort_by(|a, b| a.name.cmp(b.name)),
+        SortOrder::Activity => {
+            channels.sort_by(|a, b| (last_activity(b), a.name).cmp(&(last_activity(a), b.name)))
+        }
+    }
+
+    let mut prefix_counts: HashMap<&str, usize> = HashMap::new();
+    for entry in &channels {
+        if let Some(prefix) = name_prefix(entry.name) {
+            *prefix_counts.entry(prefix).or_default() += 1;
+        }
+    }
+    let mut groups: BTreeMap<Option<&str>, Vec<ChannelEntry<'_>>> = BTreeMap::new();
+    for entry in channels {
+        let group = name_prefix(entry.name).filter(|prefix| prefix_counts[prefix] > 1);
+        groups.entry(group).or_default().push(entry);
+    }
+
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .map(|(name, channels)| ChannelGroup { name, channels })
+        .collect();
+    if sort == SortOrder::Activity {
+        // channels are already sorted, so first one is the most recent in group
+        groups.sort_by(|a, b| {
+            let last = |group: &ChannelGroup<'_>| last_activity(&group.channels[0]);
+            (last(b), a.name).cmp(&(last(a), b.name))
+        });
+    }
+    groups
+}
+
+#[derive(Serialize)]
+struct GroupsContext<'a> {
+    groups: Vec<ChannelGroup<'a>>,
+    date: &'a Option<NaiveDate>,
+    links: &'a Links,
+}
+
+#[derive(Serialize)]
+struct ChannelsContext<'a> {
+    groups: &'a str,
+    favorites: Vec<&'a ChannelInfo>,
+    sort: SortOrder,
+    links: &'a Links,
+}
+
+fn arg<T: for<'de> Deserialize<'de>>(
+    args: &HashMap<String, Value>,
+    name: &str,
+) -> TeraResult<Option<T>> {
+    args.get(name)
+        .map(|value| {
+            serde_json::from_value(value.to_owned()).map_err(|_| {
+                TeraError::msg(format!("Function `render_channels` got invalid `{}`", name))
+            })
+        })
+        .transpose()
+}
+
+/// Sorted favorites limited to `channel_ids` viewer can see, cookie can contain anything
+fn visible_favorites(mut favorites: Vec<String>, channel_ids: &[String]) -> Vec<String> {
+    // ids of layout are sorted
+    favorites.retain(|id| channel_ids.binary_search(id).is_ok());
+    favorites.sort_unstable();
+    favorites.dedup();
+    favorites
+}
+
+fn render_groups(
+    reader: &MessagesReader,
+    (_, links, date, channel_ids, sort): &GroupsKey,
+) -> TeraResult<String> {
+    let entries = channel_ids
+        .iter()
+        .filter_map(|id| reader.get_channel(id).ok())
+        .map(|channel| ChannelEntry {
+            id: &channel.id,
+            name: &channel.name,
+            summary: reader.channel_summary(&channel.id),
+        })
+        .collect();
+    let context = Context::from_serialize(GroupsContext {
+        groups: group_channels(entries, *sort),
+        date,
+        links,
+    })?;
+    TPL.render("channel_groups.tera", &context)
+}
+
+fn render_sidebar(
+    reader: &MessagesReader,
+    (groups_key, favorites): &SidebarKey,
+) -> TeraResult<String> {
+    // groups are shared by users with same access, whatever their favorites are
+    let groups = if reader.summaries_ready() {
+        CHANNELS_LIST_CACHE
+            .get_or_try_insert_with(groups_key.clone(), |key| render_groups(reader, key))?
+    } else {
+        Arc::new(render_groups(reader, groups_key)?)
+    };
+    let mut favorites: Vec<&ChannelInfo> = favorites
+        .iter()
+        .filter_map(|id| reader.get_channel(id).ok())
+        .collect();
+    favorites.sort_by(|a, b| a.name.cmp(&b.name));
+    let (_, links, _, _, sort) = groups_key;
+    let context = Context::from_serialize(ChannelsContext {
+        groups: &groups,
+        favorites,
+        sort: *sort,
+        links,
+    })?;
+    TPL.render("channels.tera", &context)
+}
+
+pub struct RenderChannels;
+
+impl tera::Function for RenderChannels {
+    fn call(&self, args: &HashMap<String, Value>) -> TeraResult<Value> {
+        let date: Option<NaiveDate> = arg(args, "date")?;
+        let links: Links = arg(args, "links")?.unwrap_or_else(Links::server);
+        let sidebar: SidebarPrefs = arg(args, "sidebar")?.unwrap_or_default();
+        let channel_ids: Vec<String> = arg(args, "channels")?
+            .ok_or_else(|| TeraError::msg("Function `render_channels` require `channels`"))?;
+
+        let favorites = visible_favorites(sidebar.favorites, &channel_ids);
+        let reader = READER.current();
+        let key = (
+            (reader.loaded_at(), links, date, channel_ids, sidebar.sort),
+            favorites,
+        );
+        // rendered without badges while summaries are computed, those are not kept
+        let rendered = if reader.summaries_ready() {
+            SIDEBAR_CACHE.get_or_try_insert_with(key, |key| render_sidebar(&reader, key))?
+        } else {
+            Arc::new(render_sidebar(&reader, &key)?)
+        };
+        Ok(Value::String(rendered.as_ref().clone()))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(name: &'static str, last_activity: Option<NaiveDate>) -> ChannelEntry<'static> {
+        ChannelEntry {
+            id: name,
+            name,
+            summary: Some(ChannelSummary {
+                messages: 1,
+                last_activity,
+            }),
+        }
+    }
+
+    fn names<'a>(groups: &'a [ChannelGroup<'_>]) -> Vec<(Option<&'a str>, Vec<&'a str>)> {
+        groups
+            .iter()
+            .map(|group| {
+                (
+                    group.name,
+                    group.channels.iter().map(|entry| entry.name).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_group_channels() {
+        let day = |d| Some(NaiveDate::from_ymd(2020, 1, d));
+        let channels = || {
+            vec![
+                entry("team-web", day(1)),
+                entry("random", day(5)),
+                entry("team-api", day(3)),
+                entry("proj-x", day(2)),
+                entry("general", None),
+            ]
+        };
+
+        assert_eq!(
+            names(&group_channels(channels(), SortOrder::Name)),
+            vec![
+                (None, vec!["general", "proj-x", "random"]),
+                (Some("team"), vec!["team-api", "team-web"]),
+            ]
+        );
+        assert_eq!(
+            names(&group_channels(channels(), SortOrder::Activity)),
+            vec![
+                (None, vec!["random", "proj-x", "general"]),
+                (Some("team"), vec!["team-api", "team-web"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_sidebar() {
+        let reader = crate::test_support::reader();
+        let channel_ids = vec!["C1".to_string(), "C2".to_string()];
+        let favorites = visible_favorites(
+            vec!["C2".to_string(), "C9".to_string(), "C2".to_string()],
+            &channel_ids,
+        );
+        assert_eq!(favorites, vec!["C2"]);
+
+        let key = (
+            (
+                reader.loaded_at(),
+                Links::server(),
+                None,
+                channel_ids,
+                SortOrder::Name,
+            ),
+            favorites,
+        );
+        let rendered = render_sidebar(&reader, &key).unwrap();
+        let favorites = rendered.find("favorites").unwrap();
+        let random = rendered.find(r#"data-name="random""#).unwrap();
+        assert!(favorites < random);
+        assert!(rendered.contains(r#"data-name="general""#));
+    }
+
+    #[test]
+    fn test_badges_wait_for_summaries() {
+        let reader = crate::test_support::reader();
+        let render = || {
+            let general = reader.get_channel("C1").unwrap();
+            let entries = vec![ChannelEntry {
+                id: &general.id,
+                name: &general.name,
+                summary: reader.channel_summary(&general.id),
+            }];
+            let context = Context::from_serialize(GroupsContext {
+                groups: group_channels(entries, SortOrder::Name),
+                date: &None,
+                links: &Links::server(),
+            })
+            .unwrap();
+            TPL.render("channel_groups.tera", &context).unwrap()
+        };
+
+        assert!(!reader.summaries_ready());
+        assert!(!render().contains("badge"));
+        reader.compute_summaries();
+        assert!(render().contains(r#"title="Last activity 2020-10-12">4</span>"#));
+    }
+}
@@ -0,0 +1,185 @@
+//! Activity statistics of workspace and channels, computed in background by `stats` job
+
+use std::sync::Arc;
+
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::*;
+use crate::stats::{self, Report};
+
+const TOP_POSTERS: usize = 10;
+const TOP_CHANNELS: usize = 10;
+
+#[derive(Serialize)]
+struct ChannelCount<'a> {
+    id: &'a str,
+    name: &'a str,
+    messages: usize,
+}
+
+#[derive(Serialize)]
+struct StatsReport<'a> {
+    /// `None` for whole workspace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_id: Option<&'a str>,
+    /// Most active channels, only for whole workspace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_channels: Option<Vec<ChannelCount<'a>>>,
+    computed_at: DateTime<Utc>,
+    #[serde(flatten)]
+    report: Arc<Report>,
+}
+
+#[derive(Serialize)]
+struct StatsContext<'a> {
+    layout: LayoutContext<'a>,
+    channel: Option<&'a ChannelInfo>,
+    #[serde(flatten)]
+    stats: StatsReport<'a>,
+}
+
+fn user_name(reader: &MessagesReader, user_id: &str) -> String {
+    reader.get_user_info(user_id).map_or_else(
+        |_| user_id.to_string(),
+        |user| user.display_name().to_string(),
+    )
+}
+
+struct LoadedStats<'a> {
+    channel: Option<&'a ChannelInfo>,
+    stats: StatsReport<'a>,
+    version: caching::PageVersion,
+}
+
+/// Statistics of one channel, or of all channels viewer can see
+fn load_stats<'a>(
+    req: &HttpRequest,
+    reader: &'a MessagesReader,
+    viewer: &Viewer,
+    channel_id: Option<&'a str>,
+) -> Result<LoadedStats<'a>> {
+    let channel = channel_id
+        .map(|channel_id| {
+            reader
+                .get_channel(channel_id)
+                .ok()
+                .filter(|channel| viewer.access.can_view(channel))
+                .ok_or(WebError::ChannelNotFound)
+        })
+        .transpose()?;
+    let computed = stats::get(reader).ok_or(WebError::NotReady("Statistics"))?;
+    let version = caching::PageVersion::new(req, reader, viewer).with_time(computed.computed_at);
+
+    let user_name = |user_id: &str| user_name(reader, user_id);
+    let (report, top_channels) = if let Some(channel) = channel {
+        (computed.report(Some(channel), TOP_POSTERS, user_name), None)
+    } else {
+        let visible = viewer.access.visible_channels(reader);
+        let mut top_channels: Vec<_> = visible
+            .iter()
+            .map(|&channel| ChannelCount {
+                id: &channel.id,
+                name: &channel.name,
+                messages: computed
+                    .channel(&channel.id)
+                    .map_or(0, |activity| activity.messages()),
+            })
+            .filter(|count| count.messages > 0)
+            .collect();
+        top_channels.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.name.cmp(b.name)));
+        top_channels.truncate(TOP_CHANNELS);
+        (
+            computed.report(visible, TOP_POSTERS, user_name),
+            Some(top_channels),
+        )
+    };
+    let stats = StatsReport {
+        channel_id,
+        top_channels,
+        computed_at: computed.computed_at.into(),
+        report,
+    };
+    Ok(LoadedStats {
+        channel,
+        stats,
+        version,
+    })
+}
+
+fn api_response(
+    req: &HttpRequest,
+    reader: &MessagesReader,
+    viewer: &Viewer,
+    channel_id: Option<&str>,
+) -> Result<HttpResponse> {
+    let loaded = load_stats(req, reader, viewer, channel_id)?;
+    if let Some(response) = caching::not_modified(req, &loaded.version) {
+        return Ok(response);
+    }
+    let mut builder = HttpResponse::Ok();
+    caching::set_page_headers(&mut builder, &loaded.version);
+    Ok(builder.json(loaded.stats))
+}
+
+fn page_response(
+    req: &HttpRequest,
+    reader: &MessagesReader,
+    viewer: &Viewer,
+    sidebar: SidebarPrefs,
+    channel_id: Option<&str>,
+) -> Result<HttpResponse> {
+    let loaded = load_stats(req, reader, viewer, channel_id)?;
+    if let Some(response) = caching::not_modified(req, &loaded.version) {
+        return Ok(response);
+    }
+    let context = StatsContext {
+        layout: layout_context(reader, viewer).with_sidebar(sidebar),
+        channel: loaded.channel,
+        stats: loaded.stats,
+    };
+    render_response("stats.tera", &context, &loaded.version)
+}
+
+#[get("/api/stats")]
+async fn api_workspace_stats(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+) -> Result<HttpResponse> {
+    api_response(&req, &reader, &viewer, None)
+}
+
+#[get("/api/{channel}/stats")]
+async fn api_channel_stats(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse> {
+    let channel_id = path.into_inner().0;
+    api_response(&req, &reader, &viewer, Some(&channel_id))
+}
+
+#[get("/stats")]
+async fn workspace_stats(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+) -> Result<HttpResponse> {
+    page_response(&req, &reader, &viewer, sidebar, None)
+}
+
+#[get("/{channel}/stats")]
+async fn channel_stats(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    path: web::Path<(String,)>,
+) -> Result<HttpResponse> {
+    let channel_id = path.into_inner().0;
+    page_response(&req, &reader, &viewer, sidebar, Some(&channel_id))
+}
@@ -0,0 +1,46 @@
+//! Thread pages, rendered only by static export as live server shows replies within days
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use super::view_day::{render_message_list, Message};
+use super::*;
+use crate::reader::{floating_timestamp::to_slack_ts, Entry};
+
+#[derive(Serialize)]
+struct ThreadContext<'a> {
+    layout: LayoutContext<'a>,
+    channel: &'a ChannelInfo,
+    /// Day of thread parent
+    date: NaiveDate,
+    ts: String,
+    messages: String,
+}
+
+/// Thread parent followed by its replies, in order of posting
+pub fn render_static(
+    reader: &MessagesReader,
+    channel: &ChannelInfo,
+    date: NaiveDate,
+    thread: &[&Entry],
+    links: Links,
+) -> anyhow::Result<String> {
+    let ts = thread
+        .first()
+        .map(|parent| to_slack_ts(&parent.timestamp))
+        .unwrap_or_default();
+    let messages = render_message_list(
+        thread.iter().map(|entry| Message::new(entry)).collect(),
+        &links,
+    )?;
+    render_page(
+        "thread.tera",
+        &ThreadContext {
+            layout: layout_context(reader, &Viewer::unrestricted()).with_links(links),
+            channel,
+            date,
+            ts,
+            messages,
+        },
+    )
+}
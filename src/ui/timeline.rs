@@ -0,0 +1,185 @@
+//! Messages of all visible channels on one day merged into single stream
+
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use super::view_day::{render_message_list, Message};
+use super::*;
+
+#[derive(Serialize)]
+struct TimelineContext<'a> {
+    layout: LayoutContext<'a>,
+    date: NaiveDate,
+    prev: NaiveDate,
+    next: NaiveDate,
+    /// Visible channels to choose filter from, sorted by name
+    channels: Vec<&'a ChannelInfo>,
+    selected: &'a [String],
+    user: &'a str,
+    count: usize,
+    messages: String,
+}
+
+/// `channel` can be repeated, query is parsed as pairs to keep all of them
+fn query_values<'a>(query: &'a [(String, String)], name: &'a str) -> impl Iterator<Item = &'a str> {
+    query
+        .iter()
+        .filter(move |(key, value)| key == name && !value.trim().is_empty())
+        .map(|(_, value)| value.trim())
+}
+
+#[get("/day/{date}")]
+async fn timeline(
+    req: HttpRequest,
+    reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
+    path: web::Path<(NaiveDate,)>,
+    query: web::Query<Vec<(String, String)>>,
+) -> Result<HttpResponse> {
+    let date = path.into_inner().0;
+    let mut visible = viewer.access.visible_channels(&reader);
+    visible.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // hidden channels are reported same as unknown ones
+    let selected = query_values(&query, "channel")
+        .map(|channel| {
+            reader
+                .resolve_channel(channel)
+                .ok()
+                .filter(|channel| viewer.access.can_view(channel))
+                .map(|channel| channel.id.clone())
+                .ok_or(WebError::ChannelNotFound)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let user = query_values(&query, "user").next().unwrap_or_default();
+    let user_id = if user.is_empty() {
+        None
+    } else {
+        let user = reader
+            .resolve_user(user)
+            .map_err(|_| WebError::UserNotFound)?;
+        Some(user.id.as_str())
+    };
+
+    // channels without history on that day, or with the day outside of retention, are skipped
+    let channels: Vec<_> = visible
+        .iter()
+        .filter(|channel| selected.is_empty() || selected.contains(&channel.id))
+        .filter_map(|channel| {
+            reader
+                .day_version(&channel.id, &date)
+                .ok()
+                .map(|version| (*channel, version))
+        })
+        .collect();
+    let version = channels.iter().fold(
+        caching::PageVersion::new(&req, &reader, &viewer),
+        |version, (_, day_version)| version.with_day(*day_version),
+    );
+    if let Some(response) = caching::not_modified(&req, &version) {
+        return Ok(response);
+    }
+
+    let days = channels
+        .iter()
+        .map(|(channel, _)| {
+            reader
+                .channel_messages_parse(&channel.id, &date)
+                .map(|messages| (*channel, messages))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(WebError::CorruptData)?;
+    let mut entries: Vec<_> = days
+        .iter()
+        .flat_map(|(channel, messages)| {
+            messages
+                .messages
+                .iter()
+                .filter(|entry| user_id.map_or(true, |user_id| entry.user_id == user_id))
+                .map(move |entry| (*channel, entry))
+        })
+        .collect();
+    // stable, so messages with same timestamp keep order of their channel
+    entries.sort_by_key(|(_, entry)| entry.timestamp);
+    let count = entries.len();
+    let messages = render_message_list(
+        entries
+            .into_iter()
+            .map(|(channel, entry)| Message::new(entry).with_channel(channel, date))
+            .collect(),
+        &Links::server(),
+    )
+    .map_err(WebError::Template)?;
+
+    let context = TimelineContext {
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+        date,
+        prev: date.pred(),
+        next: date.succ(),
+        channels: visible,
+        selected: &selected,
+        user,
+        count,
+        messages,
+    };
+    render_response("timeline.tera", &context, &version)
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::test_support;
+
+    async fn get(uri: &str) -> (StatusCode, String) {
+        test_support::init_config();
+        let mut app = test::init_service(App::new().service(timeline)).await;
+        let response =
+            test::call_service(&mut app, test::TestRequest::get().uri(uri).to_request()).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Message texts in order they appear on page
+    fn texts(body: &str) -> Vec<&str> {
+        let mut found: Vec<_> = [
+            "Lunch?",
+            "Sure",
+            "Random thought",
+            "Anyone here?",
+            "Too late",
+        ]
+        .iter()
+        .filter_map(|text| body.find(*text).map(|pos| (pos, *text)))
+        .collect();
+        found.sort_unstable();
+        found.into_iter().map(|(_, text)| text).collect()
+    }
+
+    #[actix_rt::test]
+    async fn test_timeline() {
+        let (status, body) = get("/day/2020-10-11").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            texts(&body),
+            vec!["Lunch?", "Sure", "Random thought", "Anyone here?"]
+        );
+        // links start with root, escaped by templates
+        assert!(body.contains(r#"href="&#x2F;C2/2020-10-11">#random</a>"#));
+        assert!(body.contains(r#"href="&#x2F;C1/p/1602403260.000100""#));
+
+        let (_, body) = get("/day/2020-10-11?channel=random&channel=").await;
+        assert_eq!(texts(&body), vec!["Random thought"]);
+        let (_, body) = get("/day/2020-10-11?user=bob&channel=general").await;
+        assert_eq!(texts(&body), vec!["Sure", "Anyone here?"]);
+
+        let (status, _) = get("/day/2020-10-11?channel=unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get("/day/2020-10-11?user=nobody").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
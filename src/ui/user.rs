@@ -0,0 +1,38 @@
+//! User pages, rendered only by static export
+
+use serde::Serialize;
+
+use super::*;
+use crate::reader::User;
+
+/// Messages user posted in channel
+#[derive(Serialize)]
+pub struct ChannelActivity<'a> {
+    pub channel: &'a ChannelInfo,
+    pub messages: usize,
+}
+
+#[derive(Serialize)]
+struct UserContext<'a> {
+    layout: LayoutContext<'a>,
+    user: &'a User,
+    display_name: &'a str,
+    channels: &'a [ChannelActivity<'a>],
+}
+
+pub fn render_static(
+    reader: &MessagesReader,
+    user: &User,
+    channels: &[ChannelActivity<'_>],
+    links: Links,
+) -> anyhow::Result<String> {
+    render_page(
+        "user.tera",
+        &UserContext {
+            layout: layout_context(reader, &Viewer::unrestricted()).with_links(links),
+            user,
+            display_name: user.display_name(),
+            channels,
+        },
+    )
+}
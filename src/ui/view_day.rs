@@ -1,8 +1,12 @@
-use crate::{error::WebError};
-use actix_web::{get, HttpResponse};
-use chrono::{NaiveDate};
-use serde::Serialize;
 use super::*;
+use crate::{
+    authz::Access,
+    error::{Result, WebError},
+    reader::{floating_timestamp::to_slack_ts, ChannelMessages, Entry, PREVIEW_END, PREVIEW_START},
+};
+use actix_web::{get, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
 
 #[derive(Serialize)]
 struct ViewDayContext<'a> {
@@ -10,64 +14,175 @@ struct ViewDayContext<'a> {
     messages: String,
 }
 
+#[derive(Deserialize)]
+struct ViewDayQuery {
+    /// `preview` shows original text with redacted parts highlighted, only for admins
+    redaction: Option<String>,
+}
+
 #[derive(Serialize)]
 struct MessagesContext<'a> {
     messages: Vec<Message<'a>>,
+    links: &'a Links,
 }
 
 #[derive(Serialize)]
-struct Message<'a> {
+pub(super) struct Message<'a> {
+    /// Slack `ts`, used as anchor of message
+    ts: String,
     time: String,
     user_id: &'a String,
     text: &'a String,
+    /// Time links to permalink of message when set, static export has none
+    channel_id: Option<&'a str>,
+    /// Set when messages of several channels are shown together
+    channel: Option<ChannelLabel<'a>>,
+    highlight: bool,
+    /// Replies to thread started by message, linked to thread page of static export
+    replies: usize,
 }
 
-fn render_messages(
-    reader: &MessagesReader,
+#[derive(Serialize)]
+pub(super) struct ChannelLabel<'a> {
+    id: &'a str,
+    name: &'a str,
+    date: NaiveDate,
+}
+
+impl<'a> Message<'a> {
+    pub(super) fn new(entry: &'a Entry) -> Self {
+        Self {
+            ts: to_slack_ts(&entry.timestamp),
+            time: entry.timestamp.format("%H:%M:%S").to_string(),
+            user_id: &entry.user_id,
+            text: &entry.text,
+            channel_id: None,
+            channel: None,
+            highlight: false,
+            replies: 0,
+        }
+    }
+
+    pub(super) fn in_channel(mut self, channel_id: &'a str) -> Self {
+        self.channel_id = Some(channel_id);
+        self
+    }
+
+    pub(super) fn with_channel(mut self, channel: &'a ChannelInfo, date: NaiveDate) -> Self {
+        self.channel_id = Some(&channel.id);
+        self.channel = Some(ChannelLabel {
+            id: &channel.id,
+            name: &channel.name,
+            date,
+        });
+        self
+    }
+
+    pub(super) fn highlighted(mut self) -> Self {
+        self.highlight = true;
+        self
+    }
+
+    pub(super) fn with_replies(mut self, replies: usize) -> Self {
+        self.replies = replies;
+        self
+    }
+}
+
+pub(super) fn render_message_list(
+    messages: Vec<Message<'_>>,
+    links: &Links,
+) -> anyhow::Result<String> {
+    render_page("messages.tera", &MessagesContext { messages, links })
+}
+
+pub(super) fn render_messages(
+    messages: &ChannelMessages,
     channel_id: &str,
-    date: &NaiveDate,
-) -> Result<String, WebError> {
-    Ok(TPL
-        .render(
-            "messages.tera",
-            &Context::from_serialize(&MessagesContext {
-                messages: reader
-                    .channel_messages_parse(channel_id, date)?
-                    .messages
-                    .iter()
-                    .map(|msg| Message {
-                        time: msg.timestamp.format("%H:%M:%S").to_string(),
-                        user_id: &msg.user_id,
-                        text: &msg.text,
-                    })
-                    .collect(),
-            })
-            .unwrap(),
-        )
-        .unwrap())
+    links: &Links,
+) -> anyhow::Result<String> {
+    render_message_list(
+        messages
+            .messages
+            .iter()
+            .map(|entry| Message::new(entry).in_channel(channel_id))
+            .collect(),
+        links,
+    )
 }
 
 #[get("/{channel}/{date}")]
 async fn view_day(
+    req: HttpRequest,
     reader: DataMessagesReader,
+    viewer: Viewer,
+    sidebar: SidebarPrefs,
     parts: web::Path<(String, NaiveDate)>,
-) -> HttpResponse {
+    query: web::Query<ViewDayQuery>,
+) -> Result<HttpResponse> {
     let parts = parts.into_inner();
     let channel_id = parts.0;
     let date = parts.1;
+    if !viewer.access.can_view_id(&reader, &channel_id) {
+        return Err(WebError::ChannelNotFound);
+    }
+    // day file exists and is visible, so failures past this point mean broken data
+    let day_version = reader
+        .day_version(&channel_id, &date)
+        .map_err(|_| WebError::DateNotFound)?;
+    let version = caching::PageVersion::new(&req, &reader, &viewer).with_day(day_version);
+    if let Some(response) = caching::not_modified(&req, &version) {
+        return Ok(response);
+    }
 
-    let context = render_messages(&reader, &channel_id, &date).map(|messages| ViewDayContext {
+    let preview =
+        query.redaction.as_deref() == Some("preview") && matches!(viewer.access, Access::All);
+    let messages = if preview {
+        let messages = reader
+            .channel_messages_preview(&channel_id, &date)
+            .map_err(WebError::CorruptData)?;
+        render_messages(&messages, &channel_id, &Links::server())
+            .map_err(WebError::Template)?
+            .replace(PREVIEW_START, "<mark class=\"redacted\">")
+            .replace(PREVIEW_END, "</mark>")
+    } else {
+        let messages = reader
+            .channel_messages_parse(&channel_id, &date)
+            .map_err(WebError::CorruptData)?;
+        render_messages(&messages, &channel_id, &Links::server()).map_err(WebError::Template)?
+    };
+    let context = ViewDayContext {
         messages,
-        layout: layout_context(&reader),
-    });
+        layout: layout_context(&reader, &viewer).with_sidebar(sidebar),
+    };
+    render_response("view_day.tera", &context, &version)
+}
 
-    match context {
-        Ok(context) => {
-            render_response("view_day.tera", &context)
-        }
-        Err(err) => {
-            log::info!("Error rendering {} for day: {}: {:#}", channel_id, date, err);
-            render_page_not_found()
-        }
-    }
+/// Day page of static export, `replies` are counts of replies by thread `ts`
+pub fn render_static(
+    reader: &MessagesReader,
+    messages: &ChannelMessages,
+    replies: &HashMap<DateTime<Utc>, usize>,
+    links: Links,
+) -> anyhow::Result<String> {
+    let list = messages
+        .messages
+        .iter()
+        .map(|entry| {
+            let message = Message::new(entry);
+            match entry.thread_ts {
+                Some(thread_ts) if !entry.is_thread_reply() => {
+                    message.with_replies(replies.get(&thread_ts).copied().unwrap_or_default())
+                }
+                _ => message,
+            }
+        })
+        .collect();
+    render_page(
+        "view_day.tera",
+        &ViewDayContext {
+            messages: render_message_list(list, &links)?,
+            layout: layout_context(reader, &Viewer::unrestricted()).with_links(links),
+        },
+    )
 }
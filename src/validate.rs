@@ -0,0 +1,307 @@
+//! Consistency check of whole export directory
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::reader::{floating_timestamp::to_slack_ts, ChannelInfo, ChannelMessages, User};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    Unparseable,
+    BadFileName,
+    MissingChannelFolder,
+    UnknownFolder,
+    UnknownUser,
+    DuplicateTs,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    /// Path relative to data directory
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub channels: usize,
+    pub users: usize,
+    pub day_files: usize,
+    pub messages: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn add(&mut self, severity: Severity, kind: IssueKind, path: &str, message: String) {
+        match severity {
+            Severity::Error => self.errors += 1,
+            Severity::Warning => self.warnings += 1,
+        }
+        self.issues.push(Issue {
+            severity,
+            kind,
+            path: path.to_string(),
+            line: None,
+            column: None,
+            message,
+        });
+    }
+
+    fn add_parse_error(&mut self, path: &str, err: &serde_json::Error) {
+        self.add(
+            Severity::Error,
+            IssueKind::Unparseable,
+            path,
+            err.to_string(),
+        );
+        if let Some(issue) = self.issues.last_mut() {
+            issue.line = Some(err.line());
+            issue.column = Some(err.column());
+        }
+    }
+
+    /// Print issues in `path:line:column: severity: message` form followed by summary
+    pub fn print_text(&self) {
+        for issue in &self.issues {
+            let location = match (issue.line, issue.column) {
+                (Some(line), Some(column)) => format!("{}:{}:{}", issue.path, line, column),
+                _ => issue.path.clone(),
+            };
+            let severity = match issue.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            println!("{}: {}: {}", location, severity, issue.message);
+        }
+        println!(
+            "Checked {} channels, {} users, {} day files, {} messages: {} errors, {} warnings",
+            self.channels, self.users, self.day_files, self.messages, self.errors, self.warnings
+        );
+    }
+}
+
+fn load<T: DeserializeOwned>(
+    data_path: &Path,
+    name: &str,
+    report: &mut Report,
+) -> Result<Option<T>> {
+    let content =
+        read_to_string(data_path.join(name)).with_context(|| format!("Failed to read {}", name))?;
+    match serde_json::from_str(&content) {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => {
+            report.add_parse_error(name, &err);
+            Ok(None)
+        }
+    }
+}
+
+pub fn validate(data_path: &Path) -> Result<Report> {
+    let mut report = Report::default();
+
+    let mut channels: Vec<ChannelInfo> =
+        load(data_path, "channels.json", &mut report)?.unwrap_or_default();
+    for optional in &["groups.json", "mpims.json", "dms.json"] {
+        if data_path.join(optional).exists() {
+            channels.extend(
+                load::<Vec<ChannelInfo>>(data_path, optional, &mut report)?.unwrap_or_default(),
+            );
+        }
+    }
+    for channel in &mut channels {
+        if channel.name.is_empty() {
+            channel.name = channel.id.clone();
+        }
+    }
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    report.channels = channels.len();
+
+    let users: Option<Vec<User>> = load(data_path, "users.json", &mut report)?;
+    // unknown users are not reported when users list itself is broken
+    let user_ids: Option<HashSet<String>> =
+        users.map(|users| users.into_iter().map(|user| user.id).collect());
+    report.users = user_ids.as_ref().map_or(0, HashSet::len);
+
+    let mut folders = HashSet::new();
+    for entry in read_dir(data_path).context("Failed to read data directory")? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            folders.insert(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    for channel in &channels {
+        if !folders.remove(&channel.name) {
+            report.add(
+                Severity::Error,
+                IssueKind::MissingChannelFolder,
+                &channel.name,
+                format!("Channel {} ({}) has no folder", channel.name, channel.id),
+            );
+            continue;
+        }
+        validate_channel(data_path, channel, user_ids.as_ref(), &mut report)?;
+    }
+    let mut unknown_folders: Vec<_> = folders.into_iter().collect();
+    unknown_folders.sort();
+    for folder in unknown_folders {
+        report.add(
+            Severity::Warning,
+            IssueKind::UnknownFolder,
+            &folder,
+            "Folder does not belong to any known channel".to_string(),
+        );
+    }
+
+    Ok(report)
+}
+
+fn validate_channel(
+    data_path: &Path,
+    channel: &ChannelInfo,
+    user_ids: Option<&HashSet<String>>,
+    report: &mut Report,
+) -> Result<()> {
+    let mut files: Vec<_> = read_dir(data_path.join(&channel.name))
+        .with_context(|| format!("Failed to read channel folder {}", channel.name))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+
+    let mut seen: HashMap<DateTime<Utc>, String> = HashMap::new();
+    for file_name in files {
+        let path = format!("{}/{}", channel.name, file_name);
+        if NaiveDate::parse_from_str(&file_name, "%Y-%m-%d.json").is_err() {
+            report.add(
+                Severity::Warning,
+                IssueKind::BadFileName,
+                &path,
+                "File name does not match YYYY-MM-DD.json".to_string(),
+            );
+            continue;
+        }
+        report.day_files += 1;
+
+        let content = match read_to_string(data_path.join(&path)) {
+            Ok(content) => content,
+            Err(err) => {
+                report.add(
+                    Severity::Error,
+                    IssueKind::Unparseable,
+                    &path,
+                    format!("Failed to read: {}", err),
+                );
+                continue;
+            }
+        };
+        let messages: ChannelMessages = match serde_json::from_str(&content) {
+            Ok(messages) => messages,
+            Err(err) => {
+                report.add_parse_error(&path, &err);
+                continue;
+            }
+        };
+
+        for entry in messages.messages {
+            report.messages += 1;
+            let ts = to_slack_ts(&entry.timestamp);
+            if let Some(user_ids) = user_ids {
+                if !user_ids.contains(&entry.user_id) {
+                    report.add(
+                        Severity::Warning,
+                        IssueKind::UnknownUser,
+                        &path,
+                        format!("Message {} references unknown user {}", ts, entry.user_id),
+                    );
+                }
+            }
+            if let Some(first) = seen.insert(entry.timestamp, path.clone()) {
+                report.add(
+                    Severity::Error,
+                    IssueKind::DuplicateTs,
+                    &path,
+                    format!("Message {} duplicates message in {}", ts, first),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        let message = |user: &str| {
+            format!(
+                r#"{{"type": "message", "text": "hi", "user": "{}", "ts": "1602404763.211500"}}"#,
+                user
+            )
+        };
+        write(
+            "channels.json",
+            r#"[{"id": "C1", "name": "general"}, {"id": "C2", "name": "empty"}]"#,
+        );
+        write(
+            "users.json",
+            r#"[{"id": "U1", "name": "a", "profile": {"display_name": "", "real_name": ""}}]"#,
+        );
+        write(
+            "general/2020-01-01.json",
+            &format!("[{}, {}]", message("U1"), message("U2")),
+        );
+        write("general/2020-01-02.json", "[\n{\"type\": }]");
+        std::fs::write(dir.path().join("general/2020-01-03.json"), b"[\xff]").unwrap();
+        write("general/notes.txt", "");
+        write("stray/2020-01-01.json", "[]");
+
+        let report = validate(dir.path()).unwrap();
+        let kinds: Vec<_> = report.issues.iter().map(|issue| issue.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                IssueKind::MissingChannelFolder,
+                IssueKind::UnknownUser,
+                IssueKind::DuplicateTs,
+                IssueKind::Unparseable,
+                IssueKind::Unparseable,
+                IssueKind::BadFileName,
+                IssueKind::UnknownFolder,
+            ]
+        );
+        assert_eq!(report.issues[3].line, Some(2));
+        assert_eq!((report.errors, report.warnings), (4, 3));
+    }
+}
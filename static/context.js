@@ -0,0 +1,39 @@
+(function () {
+    var container = document.querySelector('.context-messages');
+    if (!container) {
+        return;
+    }
+
+    function showHighlighted() {
+        var message = container.querySelector('.msg.highlight');
+        if (message) {
+            message.scrollIntoView({ block: 'center' });
+        }
+    }
+
+    // wider context replaces current one, so the page stays on the same message
+    container.addEventListener('click', function (event) {
+        var link = event.target;
+        if (!link.classList.contains('more-context')) {
+            return;
+        }
+        event.preventDefault();
+        link.classList.add('is-loading');
+        var request = new XMLHttpRequest();
+        request.open('GET', link.getAttribute('href') + '&fragment=true');
+        request.onload = function () {
+            if (request.status !== 200) {
+                window.location.href = link.href;
+                return;
+            }
+            container.innerHTML = request.responseText;
+            showHighlighted();
+        };
+        request.onerror = function () {
+            window.location.href = link.href;
+        };
+        request.send();
+    });
+
+    showHighlighted();
+})();
@@ -0,0 +1,34 @@
+(function () {
+    var container = document.querySelector('.range-days');
+    if (!container) {
+        return;
+    }
+
+    // next page is appended in place instead of navigating away
+    container.addEventListener('click', function (event) {
+        var link = event.target;
+        if (!link.classList.contains('load-more')) {
+            return;
+        }
+        event.preventDefault();
+        link.classList.add('is-loading');
+        var request = new XMLHttpRequest();
+        request.open('GET', link.getAttribute('href') + '&fragment=true');
+        request.onload = function () {
+            if (request.status !== 200) {
+                window.location.href = link.href;
+                return;
+            }
+            var page = document.createElement('div');
+            page.innerHTML = request.responseText;
+            while (page.firstChild) {
+                container.insertBefore(page.firstChild, link);
+            }
+            container.removeChild(link);
+        };
+        request.onerror = function () {
+            window.location.href = link.href;
+        };
+        request.send();
+    });
+})();
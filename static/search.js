@@ -0,0 +1,34 @@
+(function () {
+    var input = document.getElementById('search');
+    var results = document.getElementById('search-results');
+    var index = window.SEARCH_INDEX || [];
+    var limit = 200;
+
+    function render(query) {
+        results.textContent = '';
+        query = query.trim().toLowerCase();
+        if (query.length < 2) {
+            return;
+        }
+        var found = 0;
+        for (var i = 0; i < index.length && found < limit; i++) {
+            var entry = index[i];
+            if (entry.x.toLowerCase().indexOf(query) === -1 && entry.u.toLowerCase().indexOf(query) === -1) {
+                continue;
+            }
+            found++;
+            var row = document.createElement('div');
+            row.className = 'msg';
+            var link = document.createElement('a');
+            link.href = entry.c + '/' + entry.d + '.html#' + entry.s;
+            link.textContent = '#' + entry.n + ' ' + entry.d + ' ' + entry.t;
+            row.appendChild(link);
+            row.appendChild(document.createTextNode(' ' + entry.u + ': ' + entry.x));
+            results.appendChild(row);
+        }
+    }
+
+    input.addEventListener('input', function () {
+        render(input.value);
+    });
+})();
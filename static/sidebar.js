@@ -0,0 +1,76 @@
+(function () {
+    var sidebar = document.querySelector('.channels');
+    if (!sidebar) {
+        return;
+    }
+    var maxAge = 365 * 24 * 60 * 60;
+
+    function getCookie(name) {
+        var parts = document.cookie.split('; ');
+        for (var i = 0; i < parts.length; i++) {
+            if (parts[i].indexOf(name + '=') === 0) {
+                return parts[i].substring(name.length + 1);
+            }
+        }
+        return '';
+    }
+
+    function setCookie(name, value) {
+        document.cookie = name + '=' + value + '; path=/; max-age=' + maxAge + '; samesite=lax';
+    }
+
+    function favorites() {
+        return getCookie('sidebar_favorites').split('.').filter(function (id) {
+            return id.length > 0;
+        });
+    }
+
+    // groups are cached on server for everyone, so favorites are marked here
+    var current = favorites();
+    var buttons = sidebar.querySelectorAll('.favorite');
+    for (var i = 0; i < buttons.length; i++) {
+        if (current.indexOf(buttons[i].getAttribute('data-channel')) !== -1) {
+            buttons[i].classList.add('is-favorite');
+            buttons[i].innerHTML = '&#9733;';
+            buttons[i].title = 'Remove from favorites';
+        }
+    }
+
+    sidebar.addEventListener('click', function (event) {
+        var target = event.target;
+        if (target.classList.contains('favorite')) {
+            var id = target.getAttribute('data-channel');
+            var list = favorites();
+            var index = list.indexOf(id);
+            if (index === -1) {
+                list.push(id);
+            } else {
+                list.splice(index, 1);
+            }
+            setCookie('sidebar_favorites', list.join('.'));
+            window.location.reload();
+        } else if (target.hasAttribute('data-sort')) {
+            event.preventDefault();
+            setCookie('sidebar_sort', target.getAttribute('data-sort'));
+            window.location.reload();
+        }
+    });
+
+    var filter = sidebar.querySelector('.channel-filter');
+    filter.addEventListener('input', function () {
+        var query = filter.value.trim().toLowerCase();
+        var groups = sidebar.querySelectorAll('.channel-group');
+        for (var i = 0; i < groups.length; i++) {
+            var channels = groups[i].querySelectorAll('.channel');
+            var shown = 0;
+            for (var j = 0; j < channels.length; j++) {
+                var match = channels[j].getAttribute('data-name').toLowerCase().indexOf(query) !== -1;
+                channels[j].style.display = match ? '' : 'none';
+                if (match) {
+                    shown++;
+                }
+            }
+            groups[i].style.display = shown > 0 ? '' : 'none';
+        }
+    });
+})();